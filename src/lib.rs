@@ -1,24 +1,34 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::{self, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::marker::PhantomData;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut, Index, RangeBounds};
 use std::ptr::NonNull;
+use std::sync::Arc;
+use std::time::Instant;
+
+mod sharded;
+
+pub use sharded::ShardedLruCache;
 
 pub struct Node<K, V> {
     k: K,
     v: V,
+    accesses: u64,
+    pinned: bool,
+    referenced: bool,
     prev: Option<NonNull<Node<K, V>>>,
     next: Option<NonNull<Node<K, V>>>,
 }
 
 struct KeyRef<K, V>(NonNull<Node<K, V>>);
 
-impl<K: Hash + Eq, V> Borrow<K> for KeyRef<K, V> {
-    fn borrow(&self) -> &K {
-        unsafe { &self.0.as_ref().k }
-    }
-}
-
 impl<K: Hash, V> Hash for KeyRef<K, V> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         unsafe { self.0.as_ref().k.hash(state) }
@@ -33,13 +43,47 @@ impl <K: Eq, V> PartialEq for KeyRef<K, V> {
 
 impl<K: Eq, V> Eq for KeyRef<K, V> {}
 
+// Lets the map be queried with any `Q` that `K` borrows as (including `K`
+// itself), e.g. `&str` for `String` keys.
+#[repr(transparent)]
+struct KeyWrapper<Q: ?Sized>(Q);
+
+impl<Q: ?Sized> KeyWrapper<Q> {
+    fn from_ref(k: &Q) -> &Self {
+        unsafe { &*(k as *const Q as *const KeyWrapper<Q>) }
+    }
+}
+
+impl<Q: ?Sized + Hash> Hash for KeyWrapper<Q> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<Q: ?Sized + PartialEq> PartialEq for KeyWrapper<Q> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq(&other.0)
+    }
+}
+
+impl<Q: ?Sized + Eq> Eq for KeyWrapper<Q> {}
+
+impl<K: Borrow<Q>, Q: ?Sized, V> Borrow<KeyWrapper<Q>> for KeyRef<K, V> {
+    fn borrow(&self) -> &KeyWrapper<Q> {
+        unsafe { KeyWrapper::from_ref(self.0.as_ref().k.borrow()) }
+    }
+}
+
 
 
 impl<K, V> Node<K, V> {
     fn new(k: K, v: V) -> Self {
         Node { 
-            k: k, 
-            v: v,
+            k,
+            v,
+            accesses: 0,
+            pinned: false,
+            referenced: false,
             prev: None,
             next: None,
         }
@@ -76,153 +120,4454 @@ pub struct LruCache<K, V> {
     tail: Option<NonNull<Node<K, V>>>,
     map: HashMap<KeyRef<K, V>, NonNull<Node<K, V>>>,
     cap: usize,
+    adaptive: Option<Adaptive>,
+    ghost: Option<Ghost>,
+    histogram: Option<[u64; 10]>,
+    on_access: Option<RefCell<AccessObserver<K>>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+    evictions: u64,
+    policy: Policy,
+    rng: u64,
+    pressure: Option<Pressure>,
+    stats_window: Option<RefCell<StatsWindow>>,
+    min_cap: Option<usize>,
+    on_evict: Option<EvictionHook<K, V>>,
+    #[cfg(debug_assertions)]
+    live: HashSet<NonNull<Node<K, V>>>,
     marker: PhantomData<Node<K, V>>,
 }
 
+/// Errors from constructing or configuring a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheError {
+    /// A capacity of zero was requested.
+    ZeroCapacity,
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::ZeroCapacity => f.write_str("cache capacity must be greater than zero"),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+/// Why [`LruCache::try_get`] found no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GetError {
+    /// The key is not cached.
+    NotFound,
+}
+
+impl fmt::Display for GetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetError::NotFound => f.write_str("key not found in cache"),
+        }
+    }
+}
+
+impl Error for GetError {}
+
+/// A source of values for read-through lookups, see
+/// [`LruCache::get_or_load`].
+///
+/// Closures of the form `Fn(&K) -> Option<V>` implement it too.
+pub trait Loader<K, V> {
+    /// Fetches the value for `k`, or `None` if there is none to cache.
+    fn load(&self, k: &K) -> Option<V>;
+}
+
+impl<K, V, F: Fn(&K) -> Option<V>> Loader<K, V> for F {
+    fn load(&self, k: &K) -> Option<V> {
+        self(k)
+    }
+}
+
+/// How a full cache picks the entry to evict, see [`LruCache::with_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    /// Evict the least recently used entry.
+    #[default]
+    Lru,
+    /// Evict a pseudo-randomly chosen entry, a baseline for cache-efficiency
+    /// experiments. The same seed always yields the same victims for the same
+    /// sequence of operations. Picking a victim walks the list, so each
+    /// eviction costs O(len).
+    Random { seed: u64 },
+    /// CLOCK (second chance): `get`, `get_mut` and a hit in
+    /// `get_mut_or_insert_default` only set a reference bit on the entry
+    /// instead of moving it to the MRU end. To pick a victim, a
+    /// hand sweeps from the LRU end; a referenced entry gets its bit cleared
+    /// and is moved to the MRU end, and the first unreferenced one is
+    /// evicted.
+    ///
+    /// Hits get cheaper since they don't relink the list, at the cost of
+    /// only approximating LRU: among entries used since the hand last
+    /// passed, the order of use is lost.
+    Clock,
+}
+
+type AccessObserver<K> = Box<dyn FnMut(&K, bool) + Send>;
+
+type EvictionHook<K, V> = Box<dyn FnMut(&K, &V, EvictionReason) + Send>;
+
+/// Why an entry left the cache, see [`LruCache::with_eviction_callback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvictionReason {
+    /// Evicted to make room for an insert.
+    Capacity,
+    /// Removed explicitly by the caller.
+    Manual,
+    /// Evicted because the capacity was lowered.
+    Resize,
+}
+
+struct Pressure {
+    high_water: f64,
+    callback: Box<dyn FnMut(usize, usize) + Send>,
+}
+
+// Bookkeeping for `LruCache::with_adaptive`. The counters cover the current
+// observation window and are reset every time the window is evaluated.
+struct Adaptive {
+    max_cap: usize,
+    target_hit_rate: f64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    // Oldest first, bounded to `CAP_HISTORY_LEN`.
+    history: Vec<CapChange>,
+}
+
+const CAP_HISTORY_LEN: usize = 32;
+
+impl Adaptive {
+    fn record(&mut self, old: usize, new: usize, reason: CapChangeReason) {
+        if old == new {
+            return;
+        }
+        if self.history.len() == CAP_HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(CapChange {
+            old,
+            new,
+            reason,
+            at: Instant::now(),
+        });
+    }
+}
+
+/// A capacity change of an adaptive cache, see [`LruCache::cap_history`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapChange {
+    /// Capacity before the change.
+    pub old: usize,
+    /// Capacity after the change.
+    pub new: usize,
+    /// What changed it.
+    pub reason: CapChangeReason,
+    /// When it changed.
+    pub at: Instant,
+}
+
+/// Why an adaptive cache's capacity changed, see [`CapChange`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CapChangeReason {
+    /// The cache grew itself after a window of poor hit rate.
+    Adaptive,
+    /// The capacity was set explicitly, e.g. with `set_cap`.
+    Manual,
+}
+
+// Hashes of recently evicted keys, oldest first. Hashes rather than keys so
+// evicted pairs can still be handed back whole and `K` needn't be `Clone`.
+struct Ghost {
+    hasher: RandomState,
+    hashes: VecDeque<u64>,
+    cap: usize,
+}
+
+// Outcomes of the most recent lookups for `LruCache::with_stats_window`,
+// oldest first, `true` for a hit.
+struct StatsWindow {
+    outcomes: VecDeque<bool>,
+    len: usize,
+    hits: usize,
+}
+
+/// A rough picture of a cache's heap footprint, see [`LruCache::memory_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Number of live nodes, one per cached entry.
+    pub nodes: usize,
+    /// Approximate bytes held by the node allocations.
+    pub node_bytes: usize,
+    /// Number of entries the backing map can hold without reallocating.
+    pub map_capacity: usize,
+}
+
+/// Backing map occupancy, see [`LruCache::bucket_stats`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketStats {
+    /// Number of entries in the map.
+    pub entries: usize,
+    /// Number of entries the map can hold without reallocating.
+    pub capacity: usize,
+    /// `entries / capacity`, or `0.0` for an unallocated map.
+    pub load_factor: f64,
+}
+
+/// A cache's size and counters at one point in time, see
+/// [`LruCache::snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    /// Number of cached entries.
+    pub len: usize,
+    /// The capacity.
+    pub cap: usize,
+    /// See [`LruCache::hits`].
+    pub hits: u64,
+    /// See [`LruCache::misses`].
+    pub misses: u64,
+    /// See [`LruCache::evictions`].
+    pub evictions: u64,
+    /// See [`LruCache::hit_rate`].
+    pub hit_rate: f64,
+    /// `len / cap`, which can exceed 1.0 while pinned entries hold the cache
+    /// over capacity.
+    pub utilization: f64,
+}
+
+/// Value sizes in bytes across a cache, see [`LruCache::value_size_stats`].
+/// All zero for an empty cache.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SizeStats {
+    /// Length of the smallest value.
+    pub min: usize,
+    /// Length of the largest value.
+    pub max: usize,
+    /// Mean value length.
+    pub mean: f64,
+}
+
 impl<K: Hash + Eq + PartialEq, V> LruCache<K, V> {
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero; see [`LruCache::try_new`] for a fallible
+    /// version.
     pub fn new(cap: usize) -> Self {
-        assert!(cap > 0);
-        Self {
+        Self::try_new(cap).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Creates a cache, returning an error instead of panicking if `cap` is
+    /// invalid, e.g. when it comes from untrusted configuration.
+    pub fn try_new(cap: usize) -> Result<Self, CacheError> {
+        if cap == 0 {
+            return Err(CacheError::ZeroCapacity);
+        }
+        Ok(Self {
             head: None,
             tail: None,
             map: HashMap::new(),
             cap,
+            adaptive: None,
+            ghost: None,
+            histogram: None,
+            on_access: None,
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            evictions: 0,
+            policy: Policy::Lru,
+            rng: 0,
+            pressure: None,
+            stats_window: None,
+            min_cap: None,
+            on_evict: None,
+            #[cfg(debug_assertions)]
+            live: HashSet::new(),
             marker: PhantomData,
-        }
+        })
     }
 
-    pub fn put(&mut self, k: K, v: V) -> Option<V> {
-        let node = Box::leak(Box::new(Node::new(k, v))).into();
+    /// Creates a cache whose capacity is non-zero by construction, so unlike
+    /// `new` this can never panic.
+    pub fn with_nonzero_capacity(cap: NonZeroUsize) -> Self {
+        Self::new(cap.get())
+    }
 
-        let old_node = self.map.remove(&KeyRef(node)).map(|node| {
-            self.detach(node);
-            node
+    /// Creates a cache sized for an `expected` working set plus a safety
+    /// margin, e.g. `overprovision = 0.25` for 25% headroom. The capacity is
+    /// rounded up and is at least 1. Products within float error of a whole
+    /// number count as that number, so `(100, 0.1)` gives 110, not 111.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `overprovision` is negative or NaN.
+    pub fn for_working_set(expected: usize, overprovision: f64) -> Self {
+        assert!(overprovision >= 0.0, "overprovision must be non-negative");
+        let want = expected as f64 * (1.0 + overprovision);
+        let nearest = want.round();
+        let cap = if (want - nearest).abs() <= nearest * 4.0 * f64::EPSILON {
+            nearest
+        } else {
+            want.ceil()
+        };
+        Self::new((cap as usize).max(1))
+    }
+
+    /// Creates a cache that starts at `min_cap` and grows itself, up to
+    /// `max_cap`, when it is too small for its working set.
+    ///
+    /// Hits and misses of `get`/`get_mut` and capacity evictions are counted
+    /// over a window of `capacity()` lookups. When a `put` finds the window
+    /// full, it evaluates it: if the window's hit rate is below
+    /// `target_hit_rate` and at least one eviction happened during it,
+    /// capacity grows by half (at least by one), clamped to `max_cap`. The
+    /// window is then reset either way. Capacity never shrinks automatically.
+    pub fn with_adaptive(min_cap: usize, max_cap: usize, target_hit_rate: f64) -> Self {
+        assert!(min_cap <= max_cap);
+        assert!((0.0..=1.0).contains(&target_hit_rate));
+        let mut cache = Self::new(min_cap);
+        cache.adaptive = Some(Adaptive {
+            max_cap,
+            target_hit_rate,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            history: Vec::new(),
         });
+        cache
+    }
 
-        if self.map.len() >= self.cap {
-            let tail = self.tail.unwrap();
-            self.detach(tail);
-            self.map.remove(&KeyRef(tail));
+    /// Returns the latest capacity changes of a cache built with
+    /// `with_adaptive`, oldest first, covering both its own growth and
+    /// explicit `set_cap` calls. Only the last 32 are kept; always empty for
+    /// other caches.
+    pub fn cap_history(&self) -> &[CapChange] {
+        self.adaptive
+            .as_ref()
+            .map_or(&[], |adaptive| &adaptive.history)
+    }
+
+    /// Returns the capacity the backing map ends up with once it is sized for
+    /// `cap` entries (e.g. via `reserve`), so it won't resize before holding
+    /// `cap` entries.
+    ///
+    /// This mirrors std's table growth: tiny tables hold 3 or 7 entries, and
+    /// larger ones use a power-of-two bucket count filled to at most 7/8.
+    /// The map always stores two pointers per entry, so the answer does not
+    /// depend on `K` or `V`.
+    ///
+    /// A `cap` of zero gives 0, as an empty map does not allocate. When the
+    /// bucket count for `cap` would overflow `usize`, no map can be sized for
+    /// it and this saturates to `usize::MAX`.
+    pub fn recommended_map_capacity(cap: usize) -> usize {
+        if cap == 0 {
+            0
+        } else if cap < 4 {
+            3
+        } else if cap < 8 {
+            7
+        } else {
+            cap.checked_mul(8)
+                .and_then(|c| (c / 7).checked_next_power_of_two())
+                .map_or(usize::MAX, |buckets| buckets / 8 * 7)
         }
+    }
 
-        self.attach(node);
-        self.map.insert(KeyRef(node), node);
-        old_node.map(|node| unsafe {
-            let node = Box::from_raw(node.as_ptr());
-            node.v
+    /// Creates a cache that also remembers the last `ghost_cap` evicted keys
+    /// (without their values), see [`LruCache::was_recently_evicted`].
+    pub fn with_ghost(cap: usize, ghost_cap: usize) -> Self {
+        assert!(ghost_cap > 0);
+        let mut cache = Self::new(cap);
+        cache.ghost = Some(Ghost {
+            hasher: RandomState::new(),
+            hashes: VecDeque::with_capacity(ghost_cap),
+            cap: ghost_cap,
+        });
+        cache
+    }
+
+    /// Returns whether `k` was among the last `ghost_cap` capacity evictions.
+    ///
+    /// A key that keeps showing up here is being evicted and re-fetched,
+    /// which suggests the cache is too small. Always `false` without
+    /// `with_ghost`. Keys are remembered by a 64-bit hash, so a false
+    /// positive is possible in principle but vanishingly unlikely.
+    pub fn was_recently_evicted(&self, k: &K) -> bool {
+        self.ghost.as_ref().is_some_and(|ghost| {
+            let hash = ghost.hasher.hash_one(k);
+            ghost.hashes.contains(&hash)
         })
     }
 
-    pub fn get(&mut self, k: &K) -> Option<&V> {
-        if let Some(node) = self.map.get(k) {
-            let node = *node;
-            self.detach(node);
-            self.attach(node);
-            unsafe { Some(&node.as_ref().v)}
+    /// Creates a cache that samples its fill level on every `put`, see
+    /// [`LruCache::utilization_histogram`].
+    pub fn with_utilization_histogram(cap: usize) -> Self {
+        let mut cache = Self::new(cap);
+        cache.histogram = Some([0; 10]);
+        cache
+    }
+
+    /// Returns how often each fill level was observed after a `put`, in
+    /// deciles of capacity: bucket `i` counts puts that left the cache between
+    /// `i * 10%` and `(i + 1) * 10%` full, with a full cache counted in the
+    /// last bucket. All zeros unless built with `with_utilization_histogram`.
+    pub fn utilization_histogram(&self) -> [u64; 10] {
+        self.histogram.unwrap_or_default()
+    }
+
+    /// Creates a cache that calls `on_access(key, hit)` on every lookup, e.g.
+    /// to emit tracing spans or counters.
+    ///
+    /// The hook fires from `get`, `get_mut` and `peek`, and from the
+    /// non-promoting reads built on `peek` (`Index` and `CacheView::peek`).
+    /// Other methods that happen to look keys up don't trigger it.
+    pub fn with_access_observer<F>(cap: usize, on_access: F) -> Self
+    where
+        F: FnMut(&K, bool) + Send + 'static,
+    {
+        let mut cache = Self::new(cap);
+        cache.on_access = Some(RefCell::new(Box::new(on_access)));
+        cache
+    }
+
+    /// Returns how many lookups found their key since the cache was created.
+    ///
+    /// Counted for `get`, `get_mut` and `peek` (and reads built on `peek`).
+    /// The counters are interior-mutable so `peek` can update them through
+    /// `&self`, which also means they can be read while only holding a
+    /// shared reference. They are plain `Cell`s, not atomics: the cache is
+    /// `Send` but not `Sync`, so reading them from another thread requires
+    /// the same lock that guards the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    /// Returns how many lookups missed, see [`LruCache::hits`].
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    /// Returns how many entries were evicted to respect the capacity, by
+    /// inserts or by shrinking it. Explicit removals aren't counted.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Captures length, capacity and the counters at once, e.g. for a
+    /// metrics exporter.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            len: self.map.len(),
+            cap: self.cap,
+            hits: self.hits(),
+            misses: self.misses(),
+            evictions: self.evictions,
+            hit_rate: self.hit_rate(),
+            utilization: self.map.len() as f64 / self.cap as f64,
+        }
+    }
+
+    /// Returns `hits / (hits + misses)`, or `0.0` before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits() + self.misses();
+        if lookups == 0 {
+            0.0
         } else {
-            None
+            self.hits() as f64 / lookups as f64
         }
     }
 
-    pub fn detach(&mut self, mut node: NonNull<Node<K, V>>) {
-        unsafe {
-            match node.as_mut().prev {
-                Some(mut prev) => {
-                    prev.as_mut().next = node.as_ref().next;
-                }
-                None => {
-                    self.head = node.as_ref().next;
-                }
-            }
+    /// Creates a cache that also remembers the outcome of the last `window`
+    /// lookups, so [`LruCache::recent_hit_rate`] can follow the current
+    /// workload instead of the lifetime totals behind `hit_rate`. Lookups are
+    /// the same ones counted by [`LruCache::hits`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn with_stats_window(cap: usize, window: usize) -> Self {
+        assert!(window > 0, "stats window must be greater than zero");
+        let mut cache = Self::new(cap);
+        cache.stats_window = Some(RefCell::new(StatsWindow {
+            outcomes: VecDeque::with_capacity(window),
+            len: window,
+            hits: 0,
+        }));
+        cache
+    }
 
-            match node.as_mut().next {
-                Some(mut next) => {
-                    next.as_mut().prev = node.as_ref().prev;
-                }
-                None => {
-                    self.tail = node.as_ref().prev;
-                }
-            }
+    /// Returns the hit rate over the last lookups kept by
+    /// `with_stats_window`, or `0.0` before any lookup or without a window.
+    pub fn recent_hit_rate(&self) -> f64 {
+        let Some(window) = &self.stats_window else {
+            return 0.0;
+        };
+        let window = window.borrow();
+        if window.outcomes.is_empty() {
+            0.0
+        } else {
+            window.hits as f64 / window.outcomes.len() as f64
+        }
+    }
 
-            node.as_mut().prev = None;
-            node.as_mut().next = None;
+    /// Creates a cache that calls `on_pressure(len, cap)` after every `put`
+    /// that leaves it at or above `high_water` (a fraction of capacity, e.g.
+    /// `0.9`) full, so callers can apply backpressure or flush in the
+    /// background before the cache starts thrashing.
+    ///
+    /// The mark is `ceil(cap * high_water)` entries (at least one), computed
+    /// against the capacity at the time of the `put`.
+    pub fn with_pressure_callback<F>(cap: usize, high_water: f64, on_pressure: F) -> Self
+    where
+        F: FnMut(usize, usize) + Send + 'static,
+    {
+        assert!(high_water > 0.0 && high_water <= 1.0);
+        let mut cache = Self::new(cap);
+        cache.pressure = Some(Pressure {
+            high_water,
+            callback: Box::new(on_pressure),
+        });
+        cache
+    }
+
+    /// Creates a cache that evicts according to `policy` when full.
+    ///
+    /// Recency is still tracked as usual whatever the policy, so methods that
+    /// talk about the MRU/LRU ends keep their meaning. The exception is
+    /// [`Policy::Clock`], under which `get`, `get_mut` and
+    /// `get_mut_or_insert_default` hits leave the order alone and the list
+    /// order is what the clock hand sweeps.
+    pub fn with_policy(cap: usize, policy: Policy) -> Self {
+        let mut cache = Self::new(cap);
+        cache.policy = policy;
+        if let Policy::Random { seed } = policy {
+            cache.rng = seed;
         }
+        cache
     }
 
-    fn attach(&mut self, mut node: NonNull<Node<K, V>>) {
-        match self.head {
-            Some(mut head) => {
-                unsafe {
-                    head.as_mut().prev = Some(node);
-                    node.as_mut().next = Some(head);
-                    node.as_mut().prev = None;
-                }
-                self.head = Some(node);
+    /// Creates a cache that calls `on_evict(key, value, reason)` as entries
+    /// leave it, e.g. to write back only capacity evictions.
+    ///
+    /// - [`EvictionReason::Capacity`]: inserts that evict (`put` and the
+    ///   other inserting methods, including the trailing trim of
+    ///   `put_batch`);
+    /// - [`EvictionReason::Resize`]: `set_cap` and `with_temp_cap` shrinking
+    ///   the cache, including when the guard restores a smaller capacity;
+    /// - [`EvictionReason::Manual`]: `remove`, `take` and
+    ///   `OccupiedEntry::remove`, and the methods built on them
+    ///   (`remove_many`, `move_to`).
+    ///
+    /// Bulk removals such as `clear`, `pop_lru_n` or `evict_where` hand the
+    /// entries back or drop them without calling the hook.
+    pub fn with_eviction_callback<F>(cap: usize, on_evict: F) -> Self
+    where
+        F: FnMut(&K, &V, EvictionReason) + Send + 'static,
+    {
+        let mut cache = Self::new(cap);
+        cache.on_evict = Some(Box::new(on_evict));
+        cache
+    }
+
+    /// Creates a cache whose capacity can never be shrunk below `floor`,
+    /// see [`LruCache::min_capacity`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `floor` is greater than `cap`.
+    pub fn with_min_capacity(cap: usize, floor: usize) -> Self {
+        Self::new(cap).min_capacity(floor)
+    }
+
+    /// Sets a floor below which the capacity can never be shrunk: later
+    /// `set_cap` calls (and `with_temp_cap` and `restore`) asking for less
+    /// are clamped to `floor` instead. It chains onto the other
+    /// constructors, e.g. to keep an adaptive cache from being shrunk too
+    /// far:
+    ///
+    /// ```
+    /// use lru_cache::LruCache;
+    ///
+    /// let mut cache: LruCache<u32, u32> = LruCache::with_adaptive(64, 1024, 0.9).min_capacity(32);
+    /// cache.set_cap(1);
+    /// assert_eq!(cache.capacity(), 32);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `floor` is greater than the current capacity.
+    pub fn min_capacity(mut self, floor: usize) -> Self {
+        assert!(floor <= self.cap, "capacity floor is above the capacity");
+        self.min_cap = Some(floor);
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Changes the capacity, evicting from the LRU end if the cache now holds
+    /// more than `cap` entries. A floor from `with_min_capacity` is applied
+    /// first.
+    pub fn set_cap(&mut self, cap: usize) {
+        let cap = self.min_cap.map_or(cap, |floor| cap.max(floor));
+        assert!(cap > 0);
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.record(self.cap, cap, CapChangeReason::Manual);
+        }
+        self.cap = cap;
+        self.trim_to_cap(EvictionReason::Resize);
+    }
+
+    /// Switches to capacity `cap` until the returned guard is dropped, at
+    /// which point the original capacity is restored (evicting down to it if
+    /// needed). The guard derefs to the cache, so use it in the meantime.
+    pub fn with_temp_cap(&mut self, cap: usize) -> CapGuard<'_, K, V> {
+        let original = self.cap;
+        self.set_cap(cap);
+        CapGuard {
+            cache: self,
+            original,
+        }
+    }
+
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        self.adapt();
+        let old = self.put_node(k, v).1;
+        if let Some(histogram) = &mut self.histogram {
+            histogram[(self.map.len() * 10 / self.cap).min(9)] += 1;
+        }
+        if let Some(pressure) = &mut self.pressure {
+            let mark = ((self.cap as f64 * pressure.high_water).ceil() as usize).max(1);
+            if self.map.len() >= mark {
+                (pressure.callback)(self.map.len(), self.cap);
+            }
+        }
+        old
+    }
+
+    /// Replaces the value of a cached `k` in place with one lookup and no
+    /// allocation, promoting it, and returns the old value. Unlike `put` this
+    /// never inserts or evicts: an absent `k` gets `v` back as `Err(v)`.
+    pub fn set(&mut self, k: &K, v: V) -> Result<V, V> {
+        let Some(&node) = self.map.get(KeyWrapper::from_ref(k)) else {
+            return Err(v);
+        };
+        let old = unsafe { mem::replace(&mut (*node.as_ptr()).v, v) };
+        self.detach(node);
+        self.attach(node);
+        Ok(old)
+    }
+
+    /// Inserts `v` as `put` would if `k` is absent, or otherwise folds it into
+    /// the stored value with `merge(&mut existing, v)` and promotes the
+    /// entry, e.g. to accumulate counts.
+    pub fn put_or_merge<F: FnOnce(&mut V, V)>(&mut self, k: K, v: V, merge: F) {
+        match self.map.get(KeyWrapper::from_ref(&k)) {
+            Some(&node) => {
+                merge(unsafe { &mut (*node.as_ptr()).v }, v);
+                self.detach(node);
+                self.attach(node);
             }
             None => {
-                unsafe {
-                    node.as_mut().prev = None;
-                    node.as_mut().next = None;
-                }
-                self.head = Some(node);
-                self.tail = Some(node);
+                self.put(k, v);
             }
         }
     }
-}
 
-impl<K, V> Drop for LruCache<K, V> {
-    fn drop(&mut self) {
-        while let Some(node) = self.head.take() {
-            unsafe {
-                self.head = node.as_ref().next;
-                drop(node.as_ptr());
-            }
+    /// Like `put`, but never evicts: a new key arriving while the cache is
+    /// full is refused and handed back as `Err((k, v))`. Replacing the value
+    /// of an existing key always succeeds.
+    pub fn saturating_put(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+        if self.is_full() && !self.contains_key(&k) {
+            return Err((k, v));
         }
+        Ok(self.put(k, v))
     }
-}
 
+    /// Inserts `v` only if `k` is absent and returns whatever value is now
+    /// resident for `k`.
+    ///
+    /// On a hit the existing value is promoted and returned, and `v` is
+    /// dropped. On a miss `v` is inserted at the MRU end, evicting if full.
+    pub fn put_if_absent(&mut self, k: K, v: V) -> &V {
+        if let Some(node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            let node = *node;
+            self.detach(node);
+            self.attach(node);
+            return unsafe { &(*node.as_ptr()).v };
+        }
+
+        let node = self.alloc_node(k, v);
+        self.insert_node(node);
+        unsafe { &(*node.as_ptr()).v }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+    /// Inserts a value computed after a missed `get`, returning the entry
+    /// evicted to make room, if any.
+    ///
+    /// This splits read-through into two steps for callers that can't hold
+    /// `&mut self` while computing, e.g. across an `.await`:
+    ///
+    /// 1. call `get(&k)`; on a hit, use the value;
+    /// 2. on a miss, drop the borrow and compute the value;
+    /// 3. call `insert_computed(k, v)`.
+    ///
+    /// If someone else inserted `k` in the meantime, `v` replaces their value
+    /// (the latest computation wins), the entry is promoted, and nothing is
+    /// evicted.
+    pub fn insert_computed(&mut self, k: K, v: V) -> Option<(K, V)> {
+        if let Some(&node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            unsafe { (*node.as_ptr()).v = v };
+            self.detach(node);
+            self.attach(node);
+            return None;
+        }
 
-    use super::*;
+        let node = self.alloc_node(k, v);
+        self.insert_node(node)
+    }
 
-    #[test]
-    fn it_works() {
-        let node = Node::new("k", "v");
-        assert_eq!(node.k, "k");
-        assert_eq!(node.v, "v");
+    /// Returns the value for `k`, promoting it, or on a miss asks `loader`
+    /// and caches what it returns, evicting if full. A `None` from the loader
+    /// caches nothing.
+    pub fn get_or_load<L: Loader<K, V>>(&mut self, k: K, loader: &L) -> Option<&V> {
+        if let Some(&node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            self.detach(node);
+            self.attach(node);
+            return Some(unsafe { &(*node.as_ptr()).v });
+        }
+
+        let v = loader.load(&k)?;
+        let node = self.alloc_node(k, v);
+        self.insert_node(node);
+        Some(unsafe { &(*node.as_ptr()).v })
     }
 
-    #[test]
-    fn it_works2() {
-        let mut m = HashMap::new();
-        m.insert(Node::new(1, 1), 1);
+    /// Inserts `k` and returns a reference to the new value, unless `k` is
+    /// already present.
+    ///
+    /// An occupied key is neither overwritten nor promoted; the error hands
+    /// the attempted pair back along with the value already stored.
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<&V, OccupiedError<'_, K, V>> {
+        if let Some(node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            let existing = unsafe { &(*node.as_ptr()).v };
+            return Err(OccupiedError {
+                key: k,
+                value: v,
+                existing,
+            });
+        }
 
-        let v = m.get(&1);
-        assert_eq!(v, Some(&1));
+        let node = self.alloc_node(k, v);
+        self.insert_node(node);
+        Ok(unsafe { &(*node.as_ptr()).v })
     }
 
+    /// Returns the value for `k`, promoting it, or on a miss inserts `f(&k)`.
+    ///
+    /// The factory sees the key before it is moved into the cache, which
+    /// suits values derived from their key. It runs at most once, and only
+    /// on a miss.
+    pub fn get_or_insert_with_key<F: FnOnce(&K) -> V>(&mut self, k: K, f: F) -> &V {
+        if let Some(&node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            self.detach(node);
+            self.attach(node);
+            return unsafe { &(*node.as_ptr()).v };
+        }
 
-    #[test]
-    fn it_works3() {
-        let mut lru = LruCache::new(3);
-        println!("it's ok");
-        assert_eq!(lru.put(1, 10), None);
-        println!("put 1");
-        assert_eq!(lru.put(2, 20), None);
-        println!("put 2");
-        assert_eq!(lru.put(3, 30), None);
-        println!("put 3");
-        assert_eq!(lru.get(&1), Some(&10));
-        println!("get 1");
-        // assert_eq!(lru.put(2, 200), Some(20));
-        // println!("put 2 again then we should get the order value of key 2 : 20");
-        // assert_eq!(lru.put(4, 40), None);
-        // println!("put 4 , should delete the ordest value from map ");
-        // assert_eq!(lru.get(&2), Some(&200));
-        // println!("get 2");
+        let v = f(&k);
+        let node = self.alloc_node(k, v);
+        self.insert_node(node);
+        unsafe { &(*node.as_ptr()).v }
+    }
+
+    /// Returns the value for `k` without promoting it, or inserts `f()` on a
+    /// miss and returns the new value.
+    ///
+    /// Reads and writes are deliberately asymmetric: a hit leaves the recency
+    /// order untouched, while a miss inserts the new entry at the MRU end
+    /// (evicting the LRU entry if the cache is full), exactly like `put`.
+    pub fn peek_or_insert_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &V {
+        if let Some(node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            let node = *node;
+            return unsafe { &(*node.as_ptr()).v };
+        }
+
+        let node = self.alloc_node(k, f());
+        self.insert_node(node);
+        unsafe { &(*node.as_ptr()).v }
+    }
+
+    pub fn get(&mut self, k: &K) -> Option<&V> {
+        self.get_mut(k).map(|v| &*v)
+    }
+
+    /// Looks `k` up, promoting it only if `promote` is set: `get_with(k, true)`
+    /// is `get` and `get_with(k, false)` is `peek`, for callers that decide
+    /// per lookup.
+    pub fn get_with(&mut self, k: &K, promote: bool) -> Option<&V> {
+        if promote {
+            self.get(k)
+        } else {
+            self.peek(k)
+        }
+    }
+
+    /// Hints that `get(k)` is coming soon, e.g. one iteration ahead in a
+    /// lookup loop: the map lookup is done now and, on x86_64, the node is
+    /// prefetched into cache. Recency and the hit/miss counters are
+    /// unchanged, and elsewhere this is just the lookup.
+    pub fn prefetch(&self, k: &K) {
+        let Some(node) = self.map.get(KeyWrapper::from_ref(k)) else {
+            return;
+        };
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            _mm_prefetch::<_MM_HINT_T0>(node.as_ptr() as *const i8);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = node;
+    }
+
+    /// Like `get`, but reports a miss as an error so it composes with `?`.
+    ///
+    /// `GetError` is non-exhaustive so that variants with their own miss
+    /// semantics (such as expiry) can add reasons later.
+    pub fn try_get(&mut self, k: &K) -> Result<&V, GetError> {
+        self.get(k).ok_or(GetError::NotFound)
+    }
+
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+        let node = self.map.get(KeyWrapper::from_ref(k)).copied();
+        self.record_access(k, node.is_some());
+        if let Some(adaptive) = &mut self.adaptive {
+            match node {
+                Some(_) => adaptive.hits += 1,
+                None => adaptive.misses += 1,
+            }
+        }
+
+        if let Some(node) = node {
+            self.touch(node);
+            unsafe {
+                (*node.as_ptr()).accesses += 1;
+                Some(&mut (*node.as_ptr()).v)
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value for `k`, promoting it, or
+    /// inserts `V::default()` on a miss.
+    ///
+    /// Handy for frequency counters: `*cache.get_mut_or_insert_default(k) += 1`
+    /// keeps hot counters resident while cold ones age out.
+    pub fn get_mut_or_insert_default(&mut self, k: K) -> &mut V
+    where
+        V: Default,
+    {
+        if let Some(node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            let node = *node;
+            self.touch(node);
+            return unsafe { &mut (*node.as_ptr()).v };
+        }
+
+        let node = self.alloc_node(k, V::default());
+        self.insert_node(node);
+        unsafe { &mut (*node.as_ptr()).v }
+    }
+
+    /// Returns the value for the borrowed key `k`, promoting it, or on a miss
+    /// calls `f` to build the owned key and value and inserts them.
+    ///
+    /// A hit never constructs an owned key. If `f` fails, nothing is inserted
+    /// and the error is returned. `f` is expected to return a key equal to
+    /// `k`; the entry is stored under whatever key it returns.
+    pub fn get_or_try_insert_ref<Q, F, E>(&mut self, k: &Q, f: F) -> Result<&V, E>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce() -> Result<(K, V), E>,
+    {
+        if let Some(node) = self.map.get(KeyWrapper::from_ref(k)) {
+            let node = *node;
+            self.detach(node);
+            self.attach(node);
+            return Ok(unsafe { &(*node.as_ptr()).v });
+        }
+
+        let (k, v) = f()?;
+        let (node, _) = self.put_node(k, v);
+        Ok(unsafe { &(*node.as_ptr()).v })
+    }
+
+    /// Returns the value for `k`, promoting it, or on a miss caches the `Ok`
+    /// result of `f`. Errors are never cached: on `Err` the cache is left
+    /// untouched (nothing is inserted or evicted) and the error is returned,
+    /// so the next lookup for `k` runs `f` again.
+    pub fn get_or_insert_ok<F, E>(&mut self, k: K, f: F) -> Result<&V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        if let Some(&node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            self.detach(node);
+            self.attach(node);
+            return Ok(unsafe { &(*node.as_ptr()).v });
+        }
+
+        let v = f()?;
+        let node = self.alloc_node(k, v);
+        self.insert_node(node);
+        Ok(unsafe { &(*node.as_ptr()).v })
+    }
+
+    /// Inserts every item from `iter` in order, then evicts down to capacity
+    /// in one trailing pass instead of evicting on every insert.
+    ///
+    /// The cache is transiently over capacity while the batch is applied.
+    /// Under the default LRU policy the survivors are the same as for
+    /// repeated `put`s: the most recently inserted `cap` distinct keys.
+    pub fn put_batch<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.upsert(k, v, false);
+        }
+        self.trim_to_cap(EvictionReason::Capacity);
+    }
+
+    /// Inserts the whole batch if its new keys fit in the spare capacity, so
+    /// nothing is evicted, or otherwise inserts nothing and hands the batch
+    /// back. Keys already cached (or repeated within the batch) don't need
+    /// extra room; their values are replaced as by `put`.
+    pub fn insert_all_or_nothing<I>(&mut self, iter: I) -> Result<(), Vec<(K, V)>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let items: Vec<(K, V)> = iter.into_iter().collect();
+        let new_keys = items
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|k| !self.contains_key(*k))
+            .collect::<HashSet<_>>()
+            .len();
+        if new_keys > self.cap.saturating_sub(self.map.len()) {
+            return Err(items);
+        }
+
+        for (k, v) in items {
+            self.upsert(k, v, true);
+        }
+        Ok(())
+    }
+
+    /// Gets the entry for `k` for in-place manipulation.
+    ///
+    /// Looking up an occupied entry counts as a use and promotes it.
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V> {
+        match self.map.get(KeyWrapper::from_ref(&k)) {
+            Some(&node) => {
+                self.detach(node);
+                self.attach(node);
+                Entry::Occupied(OccupiedEntry { cache: self, node })
+            }
+            None => Entry::Vacant(VacantEntry {
+                cache: self,
+                key: k,
+            }),
+        }
+    }
+
+    /// Calls `f` with the current value of `k` and, if it returns a new value,
+    /// stores it. Either way the entry is promoted and the value now stored
+    /// is returned. Returns `None`, without calling `f`, if `k` is absent.
+    pub fn get_and_update<F: FnOnce(&V) -> Option<V>>(&mut self, k: &K, f: F) -> Option<&V> {
+        let node = *self.map.get(KeyWrapper::from_ref(k))?;
+        self.detach(node);
+        self.attach(node);
+        let v = unsafe { &mut (*node.as_ptr()).v };
+        if let Some(new) = f(v) {
+            *v = new;
+        }
+        Some(v)
+    }
+
+    /// Returns the value for `k` without updating its recency.
+    pub fn peek(&self, k: &K) -> Option<&V> {
+        let node = self.map.get(KeyWrapper::from_ref(k));
+        self.record_access(k, node.is_some());
+        node.map(|node| unsafe { &node.as_ref().v })
+    }
+
+    /// Returns the most recently used entry (the "first" one, at `head`)
+    /// without changing recency.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.head.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            (&node.k, &node.v)
+        })
+    }
+
+    /// Returns the least recently used entry (the "last" one, at `tail`), i.e.
+    /// the next eviction candidate, without changing recency.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.tail.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            (&node.k, &node.v)
+        })
+    }
+
+    /// Returns whether `k` is present, without changing recency. Accepts any
+    /// borrowed form of the key, e.g. `&str` for `String` keys.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(KeyWrapper::from_ref(k))
+    }
+
+    /// Returns whether every key in `keys` is cached, without changing
+    /// recency. Stops at the first missing key; an empty slice is `true`.
+    pub fn contains_all(&self, keys: &[K]) -> bool {
+        keys.iter().all(|k| self.contains_key(k))
+    }
+
+    /// Returns whether any key in `keys` is cached, without changing
+    /// recency. Stops at the first present key; an empty slice is `false`.
+    pub fn contains_any(&self, keys: &[K]) -> bool {
+        keys.iter().any(|k| self.contains_key(k))
+    }
+
+    /// Iterates over the entries in MRU→LRU order without changing recency.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            head: self.head,
+            tail: self.tail,
+            len: self.map.len(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Iterates over the entries as an unordered map, without changing
+    /// recency. Use `iter` when recency order matters; this makes no promise
+    /// about order at all.
+    pub fn map_view(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.map.values().map(|node| unsafe {
+            let node = &*node.as_ptr();
+            (&node.k, &node.v)
+        })
+    }
+
+    /// Calls `f` on every entry in MRU→LRU order without changing recency.
+    pub fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        for (k, v) in self.iter() {
+            f(k, v);
+        }
+    }
+
+    /// Calls `f` on every entry in MRU→LRU order, letting it update values in
+    /// place, without changing recency.
+    pub fn for_each_mut<F: FnMut(&K, &mut V)>(&mut self, mut f: F) {
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            unsafe {
+                let node = &mut *node.as_ptr();
+                f(&node.k, &mut node.v);
+                cur = node.next;
+            }
+        }
+    }
+
+    /// Iterates from the entry for `k` toward the LRU end, i.e. that entry and
+    /// everything colder, without changing recency. `None` if `k` is absent.
+    pub fn iter_from<'a>(&'a self, k: &K) -> Option<impl Iterator<Item = (&'a K, &'a V)>> {
+        let start = *self.map.get(KeyWrapper::from_ref(k))?;
+        let nodes = std::iter::successors(Some(start), |node| unsafe { node.as_ref().next });
+        Some(nodes.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            (&node.k, &node.v)
+        }))
+    }
+
+    /// Consumes the cache, yielding entries oldest first (LRU→MRU), e.g. for
+    /// write-back flushes. The plain `into_iter` yields hottest first.
+    pub fn into_iter_lru(self) -> impl Iterator<Item = (K, V)> {
+        self.into_iter().rev()
+    }
+
+    /// Clears `buf` and fills it with the entries in MRU→LRU order, so hot
+    /// paths can reuse one allocation instead of collecting `iter()` anew.
+    ///
+    /// The buffer borrows from the cache, so it must be cleared or dropped
+    /// before the cache is mutated again.
+    pub fn fill_order_into<'a>(&'a self, buf: &mut Vec<(&'a K, &'a V)>) {
+        buf.clear();
+        buf.extend(self.iter());
+    }
+
+    /// Returns the key of the most recently used entry whose value equals
+    /// `v`, without changing recency. A linear scan, so best kept to small
+    /// caches.
+    pub fn key_of(&self, v: &V) -> Option<&K>
+    where
+        V: PartialEq,
+    {
+        self.iter().find(|(_, value)| *value == v).map(|(k, _)| k)
+    }
+
+    /// Returns the entry at recency position `n`, counting from 0 at the MRU
+    /// end, without changing recency. Walks `n` nodes, so it costs O(n).
+    pub fn peek_nth(&self, n: usize) -> Option<(&K, &V)> {
+        self.iter().nth(n)
+    }
+
+    /// Returns up to `n` values from the MRU end, hottest first, without
+    /// changing recency.
+    pub fn mru_values(&self, n: usize) -> Vec<&V> {
+        self.iter().take(n).map(|(_, v)| v).collect()
+    }
+
+    /// Returns up to `n` values from the LRU end, coldest first, without
+    /// changing recency.
+    pub fn lru_values(&self, n: usize) -> Vec<&V> {
+        self.iter().rev().take(n).map(|(_, v)| v).collect()
+    }
+
+    /// Returns up to `n` entries from the LRU end, i.e. the next eviction
+    /// candidates in eviction order, without changing recency. The
+    /// non-destructive counterpart of [`LruCache::pop_lru_n`].
+    pub fn peek_lru_n(&self, n: usize) -> Vec<(&K, &V)> {
+        self.iter().rev().take(n).collect()
+    }
+
+    /// Returns the entry `get`/`get_mut` returned most often, preferring the
+    /// more recently used on ties, without changing recency.
+    pub fn hottest(&self) -> Option<(&K, &V)> {
+        self.extreme_by_accesses(|candidate, best| candidate > best)
+    }
+
+    /// Returns the entry `get`/`get_mut` returned least often, preferring the
+    /// less recently used on ties, without changing recency.
+    pub fn coldest(&self) -> Option<(&K, &V)> {
+        self.extreme_by_accesses(|candidate, best| candidate <= best)
+    }
+
+    // Walks MRU→LRU keeping the node for which `replaces(its count, best
+    // count so far)` last held.
+    fn extreme_by_accesses<F: Fn(u64, u64) -> bool>(&self, replaces: F) -> Option<(&K, &V)> {
+        let mut best: Option<&Node<K, V>> = None;
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            let node = unsafe { &*node.as_ptr() };
+            if best.is_none_or(|best| replaces(node.accesses, best.accesses)) {
+                best = Some(node);
+            }
+            cur = node.next;
+        }
+        best.map(|node| (&node.k, &node.v))
+    }
+
+    /// Tallies entries into the buckets returned by `key_fn`, e.g. entries per
+    /// tenant, without changing recency.
+    pub fn count_by<F, G>(&self, key_fn: F) -> HashMap<G, usize>
+    where
+        F: Fn(&K, &V) -> G,
+        G: Hash + Eq,
+    {
+        let mut counts = HashMap::new();
+        for (k, v) in self.iter() {
+            *counts.entry(key_fn(k, v)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Returns whether both caches hold the same key→value pairs in the same
+    /// MRU→LRU order. Capacity and configuration are not compared.
+    pub fn same_order_as(&self, other: &LruCache<K, V>) -> bool
+    where
+        V: PartialEq,
+    {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+
+    /// Returns the entries whose keys fall in `range`, sorted by key, without
+    /// changing recency. The cache isn't ordered by key, so this walks every
+    /// entry and sorts the matches.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self.iter().filter(|(k, _)| range.contains(*k)).collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// Returns a read-only handle to the cache.
+    ///
+    /// A function taking a `CacheView` can look entries up but is guaranteed
+    /// not to mutate the cache or disturb its recency order.
+    pub fn view(&self) -> CacheView<'_, K, V> {
+        CacheView { cache: self }
+    }
+
+    /// Moves `k` to the LRU end so it is the next entry to be evicted,
+    /// returning whether it was found.
+    pub fn demote(&mut self, k: &K) -> bool {
+        let Some(&node) = self.map.get(KeyWrapper::from_ref(k)) else {
+            return false;
+        };
+        self.detach(node);
+        self.attach_tail(node);
+        true
+    }
+
+    /// Renames the entry `old` to `new`, keeping its value and its place in
+    /// the recency order.
+    ///
+    /// Returns `false` and changes nothing if `old` is absent or `new` is
+    /// already present.
+    pub fn replace_key(&mut self, old: &K, new: K) -> bool {
+        if self.contains_key(&new) {
+            return false;
+        }
+        let Some(mut node) = self.map.remove(KeyWrapper::from_ref(old)) else {
+            return false;
+        };
+
+        unsafe { node.as_mut().k = new };
+        self.map.insert(KeyRef(node), node);
+        true
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.take(k).map(|(_, v)| v)
+    }
+
+    /// Moves the entry for `k` to the MRU end of `other`, e.g. to promote it
+    /// between cache tiers, returning whether `k` was present. The key and
+    /// value are moved, not cloned. `other` replaces any value it already
+    /// had for `k` and evicts as its own `put` would.
+    pub fn move_to(&mut self, k: &K, other: &mut LruCache<K, V>) -> bool {
+        match self.take(k) {
+            Some((k, v)) => {
+                other.put(k, v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the entry matching the borrowed key `k` and returns it along
+    /// with the owned key that was stored, which may carry more than `k`.
+    pub fn take<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(KeyWrapper::from_ref(k))?;
+        let (k, v) = self.remove_node(node);
+        self.report_eviction(&k, &v, EvictionReason::Manual);
+        Some((k, v))
+    }
+
+    /// Removes each of `keys`, returning the removed values in input order
+    /// with `None` for keys that weren't cached (including repeats of a key
+    /// already removed).
+    pub fn remove_many<Q>(&mut self, keys: &[&Q]) -> Vec<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        keys.iter().map(|k| self.take(*k).map(|(_, v)| v)).collect()
+    }
+
+    /// Removes every entry for which `pred` returns `true` and returns how
+    /// many were removed. This is the inverse of a `retain`: matches go.
+    pub fn evict_where<F: FnMut(&K, &V) -> bool>(&mut self, mut pred: F) -> usize {
+        let mut removed = 0;
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            let (next, matched) = unsafe {
+                let node = node.as_ref();
+                (node.next, pred(&node.k, &node.v))
+            };
+            if matched {
+                self.remove_node(node);
+                removed += 1;
+            }
+            cur = next;
+        }
+        removed
+    }
+
+    /// Walks the entries MRU first, giving `f` mutable access to each value,
+    /// and removes those for which it returns `false`. Survivors keep their
+    /// position and any changes `f` made.
+    pub fn retain_mut<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            let (next, keep) = unsafe {
+                let node = &mut *node.as_ptr();
+                (node.next, f(&node.k, &mut node.v))
+            };
+            if !keep {
+                self.remove_node(node);
+            }
+            cur = next;
+        }
+    }
+
+    /// Removes all but the most recently used entry among those holding equal
+    /// values, returning how many were removed. Survivors keep their place.
+    pub fn dedup_values(&mut self) -> usize
+    where
+        V: Hash + Eq,
+    {
+        let mut dups = Vec::new();
+        {
+            let mut seen = HashSet::new();
+            let mut cur = self.head;
+            while let Some(node) = cur {
+                let node_ref = unsafe { &*node.as_ptr() };
+                if !seen.insert(&node_ref.v) {
+                    dups.push(node);
+                }
+                cur = node_ref.next;
+            }
+        }
+
+        for &node in &dups {
+            self.remove_node(node);
+        }
+        dups.len()
+    }
+
+    /// Clones every entry into a `Vec` in MRU→LRU order, e.g. for logging,
+    /// leaving the cache and its recency untouched. The non-consuming
+    /// counterpart of `into_iter().collect()`.
+    pub fn to_vec(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Consumes the cache and returns its entries sorted by key rather than
+    /// by recency, which makes for deterministic snapshots.
+    pub fn into_sorted_vec(self) -> Vec<(K, V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Moves every entry matching `pred` to the MRU end, keeping their
+    /// relative order, and returns how many were promoted. A bulk `get` for
+    /// "these are all hot now" without counting lookups.
+    pub fn promote_where<F: FnMut(&K, &V) -> bool>(&mut self, mut pred: F) -> usize {
+        let mut promoted = 0;
+        let mut cur = self.tail;
+        // Promoted nodes land in front of the unvisited ones, so stop after
+        // one pass over the original entries.
+        for _ in 0..self.map.len() {
+            let Some(node) = cur else {
+                break;
+            };
+            let (prev, hit) = unsafe {
+                let node = node.as_ref();
+                (node.prev, pred(&node.k, &node.v))
+            };
+            if hit {
+                self.detach(node);
+                self.attach(node);
+                promoted += 1;
+            }
+            cur = prev;
+        }
+        promoted
+    }
+
+    /// Removes every entry whose value matches `pred`, e.g. all cached 404
+    /// responses, returning how many were removed.
+    pub fn invalidate_by_value<F: FnMut(&V) -> bool>(&mut self, mut pred: F) -> usize {
+        self.evict_where(|_, v| pred(v))
+    }
+
+    /// Removes and returns up to `n` entries from the LRU end, oldest first,
+    /// e.g. for periodic write-back of cold entries.
+    pub fn pop_lru_n(&mut self, n: usize) -> Vec<(K, V)> {
+        let mut popped = Vec::with_capacity(n.min(self.map.len()));
+        while popped.len() < n {
+            match self.pop_tail() {
+                Some(entry) => popped.push(entry),
+                None => break,
+            }
+        }
+        popped
+    }
+
+    /// Removes entries from the LRU end for as long as `pred` holds, stopping
+    /// at the first entry it rejects, and returns them oldest first.
+    pub fn drain_oldest_while<F: FnMut(&K, &V) -> bool>(&mut self, mut pred: F) -> Vec<(K, V)> {
+        let mut drained = Vec::new();
+        while let Some(tail) = self.tail {
+            let matches = unsafe {
+                let tail = tail.as_ref();
+                pred(&tail.k, &tail.v)
+            };
+            if !matches {
+                break;
+            }
+            drained.push(self.remove_node(tail));
+        }
+        drained
+    }
+
+    /// Removes and returns, oldest first, every entry that `get`/`get_mut`
+    /// returned fewer than `min_accesses` times since it was inserted,
+    /// regardless of how recently it was used.
+    pub fn flush_cold(&mut self, min_accesses: u64) -> Vec<(K, V)> {
+        let mut flushed = Vec::new();
+        let mut cur = self.tail;
+        while let Some(node) = cur {
+            let (prev, cold) = unsafe {
+                let node = node.as_ref();
+                (node.prev, node.accesses < min_accesses)
+            };
+            if cold {
+                flushed.push(self.remove_node(node));
+            }
+            cur = prev;
+        }
+        flushed
+    }
+
+    /// Removes every entry, oldest first, handing each pair to `f`, e.g. to
+    /// flush everything to disk. The cache is left empty even if `f` panics,
+    /// in which case the entries not yet passed to `f` are dropped.
+    pub fn drain_into<F: FnMut(K, V)>(&mut self, mut f: F) {
+        let guard = ClearOnDrop(self);
+        while let Some((k, v)) = guard.0.pop_tail() {
+            f(k, v);
+        }
+    }
+
+    /// Exempts the entry for `k` from eviction, returning whether `k` is
+    /// cached. Recency is unchanged.
+    ///
+    /// A full cache evicts its oldest unpinned entry instead. If every entry
+    /// is pinned, inserts still succeed and the cache grows past its
+    /// capacity until entries are unpinned or removed; shrinking with
+    /// `set_cap` likewise stops at the pinned entries. Explicit removals such
+    /// as `remove` or `pop_lru_n` still take pinned entries.
+    pub fn pin(&mut self, k: &K) -> bool {
+        self.set_pinned(k, true)
+    }
+
+    /// Makes a pinned entry evictable again, returning whether `k` is
+    /// cached. Recency is unchanged.
+    pub fn unpin(&mut self, k: &K) -> bool {
+        self.set_pinned(k, false)
+    }
+
+    /// Removes and frees every entry. Capacity and configuration are kept,
+    /// and so is the backing map's allocation, which makes refilling cheap;
+    /// see [`LruCache::clear_and_shrink`] to release it.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.free_all();
+    }
+
+    /// Like `clear`, but also releases the backing map's allocation, for
+    /// caches that won't be refilled soon. Refilling then pays for the map's
+    /// regrowth again. The capacity is unchanged.
+    pub fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.map.shrink_to_fit();
+    }
+
+    /// Empties the cache, sets capacity `cap` and zeroes every counter (hits,
+    /// misses, evictions, the stats window, the utilization histogram, the
+    /// ghost list, the capacity history and a random policy's state), e.g.
+    /// between test cases. Hooks, the policy and a capacity floor are kept,
+    /// and the floor applies to `cap` as it does in [`LruCache::set_cap`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero and no capacity floor is set; the floor is
+    /// applied first.
+    pub fn reset(&mut self, cap: usize) {
+        self.clear();
+        self.set_cap(cap);
+        self.hits.set(0);
+        self.misses.set(0);
+        self.evictions = 0;
+        if let Some(window) = &self.stats_window {
+            let window = &mut *window.borrow_mut();
+            window.outcomes.clear();
+            window.hits = 0;
+        }
+        if let Some(histogram) = &mut self.histogram {
+            *histogram = [0; 10];
+        }
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.hits = 0;
+            adaptive.misses = 0;
+            adaptive.evictions = 0;
+            adaptive.history.clear();
+        }
+        if let Some(ghost) = &mut self.ghost {
+            ghost.hashes.clear();
+        }
+        if let Policy::Random { seed } = self.policy {
+            self.rng = seed;
+        }
+    }
+
+    /// Captures the cache's contents, recency order and capacity so they can
+    /// be put back with [`LruCache::restore`], e.g. to roll back a batch of
+    /// mutations that failed halfway.
+    pub fn checkpoint(&self) -> Checkpoint<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::with_capacity(self.map.len());
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            let node = unsafe { node.as_ref() };
+            entries.push(SavedEntry {
+                k: node.k.clone(),
+                v: node.v.clone(),
+                accesses: node.accesses,
+                pinned: node.pinned,
+            });
+            cur = node.next;
+        }
+        Checkpoint {
+            entries,
+            cap: self.cap,
+        }
+    }
+
+    /// Replaces the current contents, order and capacity with `cp`, including
+    /// which entries were pinned and their access counts. Current entries are
+    /// freed first; hooks, policy and stats are left as they are.
+    /// The capacity goes through `set_cap`, so a `with_min_capacity` floor
+    /// still applies.
+    pub fn restore(&mut self, cp: Checkpoint<K, V>) {
+        self.clear();
+        self.set_cap(cp.cap);
+        for entry in cp.entries {
+            let node = self.push_back_new(entry.k, entry.v);
+            unsafe {
+                (*node.as_ptr()).accesses = entry.accesses;
+                (*node.as_ptr()).pinned = entry.pinned;
+            }
+        }
+    }
+
+    /// Clones the contents into a new cache of capacity `new_cap`, keeping
+    /// the `new_cap` most recently used entries if there are more, in the
+    /// same MRU→LRU order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_cap` is zero.
+    pub fn clone_with_cap(&self, new_cap: usize) -> LruCache<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut cloned = LruCache::new(new_cap);
+        for (k, v) in self.iter().take(new_cap) {
+            cloned.push_back_new(k.clone(), v.clone());
+        }
+        cloned
+    }
+
+    /// Clones the most recently used `fraction` of the entries, rounded up,
+    /// into a new cache whose capacity is scaled by the same fraction (at
+    /// least 1), e.g. a warm subset for a secondary process. `fraction` is
+    /// clamped to `(0.0, 1.0]`, so the sample is never empty unless the
+    /// cache is.
+    pub fn sample(&self, fraction: f64) -> LruCache<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let fraction = if fraction > 0.0 {
+            fraction.min(1.0)
+        } else {
+            f64::MIN_POSITIVE
+        };
+        let cap = ((self.cap as f64 * fraction).ceil() as usize).max(1);
+        let keep = (self.map.len() as f64 * fraction).ceil() as usize;
+        let mut sampled = LruCache::new(cap);
+        for (k, v) in self.iter().take(keep.min(cap)) {
+            sampled.push_back_new(k.clone(), v.clone());
+        }
+        sampled
+    }
+
+    /// Builds a new cache with the same keys, recency order and capacity,
+    /// with every value transformed by `f`. The original is left untouched.
+    pub fn map_values<W, F: FnMut(&V) -> W>(&self, mut f: F) -> LruCache<K, W>
+    where
+        K: Clone,
+    {
+        let mut mapped = LruCache::new(self.cap);
+        for (k, v) in self.iter() {
+            mapped.push_back_new(k.clone(), f(v));
+        }
+        mapped
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns whether inserting a new key would evict an entry.
+    pub fn is_full(&self) -> bool {
+        self.map.len() >= self.cap
+    }
+
+    /// Checks that the map and the list agree, returning a description of
+    /// the first problem found instead of panicking. Meant for tests, e.g.
+    /// property tests that drive a cache through random operations; it walks
+    /// the whole list, so it costs O(len).
+    pub fn validate(&self) -> Result<(), String> {
+        if self.head.is_none() != self.tail.is_none() {
+            return Err("exactly one of head and tail is set".to_string());
+        }
+        if let Some(head) = self.head {
+            if unsafe { head.as_ref().prev.is_some() } {
+                return Err("head has a prev link".to_string());
+            }
+        }
+
+        let mut len = 0;
+        let mut prev = None;
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            let node_ref = unsafe { node.as_ref() };
+            if node_ref.prev != prev {
+                return Err(format!("node at position {len} has a broken prev link"));
+            }
+            if self.map.get(&KeyRef(node)) != Some(&node) {
+                return Err(format!("node at position {len} is not mapped to itself"));
+            }
+            len += 1;
+            // Also stops a cycle, since every node on it is mapped.
+            if len > self.map.len() {
+                return Err(format!(
+                    "list is longer than the map's {} entries",
+                    self.map.len()
+                ));
+            }
+            prev = Some(node);
+            cur = node_ref.next;
+        }
+
+        if prev != self.tail {
+            return Err("tail is not the last node of the list".to_string());
+        }
+        if len != self.map.len() {
+            return Err(format!(
+                "list has {len} nodes but the map has {} entries",
+                self.map.len()
+            ));
+        }
+        #[cfg(debug_assertions)]
+        if self.live.len() != len {
+            return Err(format!(
+                "{} nodes are allocated but {len} are linked",
+                self.live.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reports how full the backing map is, to help tell a poor hasher or
+    /// key distribution apart from plain overload when lookups are slow.
+    /// `std`'s `HashMap` doesn't expose its buckets, so this is limited to
+    /// entries against capacity.
+    pub fn bucket_stats(&self) -> BucketStats {
+        let entries = self.map.len();
+        let capacity = self.map.capacity();
+        BucketStats {
+            entries,
+            capacity,
+            load_factor: if capacity == 0 {
+                0.0
+            } else {
+                entries as f64 / capacity as f64
+            },
+        }
+    }
+
+    /// Estimates the cache's heap usage without walking the list.
+    ///
+    /// `node_bytes` only counts the `Node` allocations themselves; heap data
+    /// owned by keys or values (e.g. a `String`'s buffer) is not included.
+    pub fn memory_report(&self) -> MemoryReport {
+        let nodes = self.map.len();
+        MemoryReport {
+            nodes,
+            node_bytes: nodes * mem::size_of::<Node<K, V>>(),
+            map_capacity: self.map.capacity(),
+        }
+    }
+
+    /// Rebuilds the backing map sized to the current `len`, releasing the
+    /// spare table space left behind by heavy churn.
+    ///
+    /// The list itself is untouched, so recency order is preserved.
+    pub fn compact(&mut self) {
+        self.rebuild_map(self.map.len());
+    }
+
+    /// Rebuilds the backing map with a freshly seeded hasher, e.g. for a
+    /// long-running cache worried about hash flooding.
+    ///
+    /// Nodes are reused and only re-keyed; entries, recency order and the
+    /// map's capacity are preserved.
+    pub fn rehash(&mut self) {
+        self.rebuild_map(self.map.capacity());
+    }
+
+    /// Rebuilds the backing map from the keys currently stored in the list,
+    /// preserving recency order.
+    ///
+    /// Only needed to repair a cache whose keys changed their hash or
+    /// equality while cached (e.g. through interior mutability), which
+    /// leaves the map unable to find them. If keys now compare equal, the
+    /// most recently used one is kept and the others are dropped.
+    pub fn rehash_keys(&mut self) {
+        let mut map = HashMap::with_capacity_and_hasher(self.map.capacity(), RandomState::new());
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            cur = unsafe { node.as_ref().next };
+            match map.entry(KeyRef(node)) {
+                hash_map::Entry::Occupied(_) => {
+                    self.detach(node);
+                    self.free_node(node);
+                }
+                hash_map::Entry::Vacant(slot) => {
+                    slot.insert(node);
+                }
+            }
+        }
+        self.map = map;
+    }
+
+    pub fn detach(&mut self, mut node: NonNull<Node<K, V>>) {
+        unsafe {
+            match node.as_mut().prev {
+                Some(mut prev) => {
+                    prev.as_mut().next = node.as_ref().next;
+                }
+                None => {
+                    self.head = node.as_ref().next;
+                }
+            }
+
+            match node.as_mut().next {
+                Some(mut next) => {
+                    next.as_mut().prev = node.as_ref().prev;
+                }
+                None => {
+                    self.tail = node.as_ref().prev;
+                }
+            }
+
+            node.as_mut().prev = None;
+            node.as_mut().next = None;
+        }
+    }
+
+    fn put_node(&mut self, k: K, v: V) -> (NonNull<Node<K, V>>, Option<V>) {
+        self.upsert(k, v, true)
+    }
+
+    // Inserts at the MRU end, or replaces and promotes the value of an
+    // existing entry in place. New nodes are linked only after any eviction
+    // so the victim can never be the node being inserted. With `evict` unset
+    // the cache may go over capacity; callers then trim back down to `cap`.
+    fn upsert(&mut self, k: K, v: V, evict: bool) -> (NonNull<Node<K, V>>, Option<V>) {
+        if let Some(&node) = self.map.get(KeyWrapper::from_ref(&k)) {
+            let old = unsafe { mem::replace(&mut (*node.as_ptr()).v, v) };
+            self.detach(node);
+            self.attach(node);
+            return (node, Some(old));
+        }
+
+        let node = self.alloc_node(k, v);
+        if evict {
+            self.insert_node(node);
+        } else {
+            self.map.insert(KeyRef(node), node);
+            self.attach(node);
+        }
+        (node, None)
+    }
+
+    fn trim_to_cap(&mut self, reason: EvictionReason) {
+        while self.map.len() > self.cap && self.evict(reason).is_some() {}
+    }
+
+    // Every capacity-driven eviction goes through here; the victim depends on
+    // the policy.
+    fn evict(&mut self, reason: EvictionReason) -> Option<(K, V)> {
+        let victim = match self.policy {
+            Policy::Lru => Self::unpinned_nodes(self.tail, |node| node.prev).next(),
+            Policy::Random { .. } => {
+                let unpinned = Self::unpinned_nodes(self.head, |node| node.next).count();
+                if unpinned == 0 {
+                    None
+                } else {
+                    let n = (self.next_random() % unpinned as u64) as usize;
+                    Self::unpinned_nodes(self.head, |node| node.next).nth(n)
+                }
+            }
+            Policy::Clock => {
+                // The first sweep clears every bit, so unless everything is
+                // pinned a victim turns up within two laps.
+                let mut victim = None;
+                for _ in 0..2 * self.map.len() {
+                    let Some(node) = self.tail else {
+                        break;
+                    };
+                    let (pinned, referenced) = unsafe {
+                        let node = &mut *node.as_ptr();
+                        (node.pinned, mem::take(&mut node.referenced))
+                    };
+                    if !pinned && !referenced {
+                        victim = Some(node);
+                        break;
+                    }
+                    self.detach(node);
+                    self.attach(node);
+                }
+                victim
+            }
+        };
+        let evicted = victim.map(|node| self.remove_node(node));
+        if let Some((k, v)) = &evicted {
+            self.report_eviction(k, v, reason);
+            self.evictions += 1;
+            if let Some(adaptive) = &mut self.adaptive {
+                adaptive.evictions += 1;
+            }
+            if let Some(ghost) = &mut self.ghost {
+                if ghost.hashes.len() == ghost.cap {
+                    ghost.hashes.pop_front();
+                }
+                ghost.hashes.push_back(ghost.hasher.hash_one(k));
+            }
+        }
+        evicted
+    }
+
+    fn report_eviction(&mut self, k: &K, v: &V, reason: EvictionReason) {
+        if let Some(on_evict) = &mut self.on_evict {
+            on_evict(k, v, reason);
+        }
+    }
+
+    fn set_pinned(&mut self, k: &K, pinned: bool) -> bool {
+        match self.map.get(KeyWrapper::from_ref(k)) {
+            Some(node) => {
+                unsafe { (*node.as_ptr()).pinned = pinned };
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Walks the list from `start` in the direction given by `step`, skipping
+    // pinned nodes.
+    fn unpinned_nodes<F>(
+        start: Option<NonNull<Node<K, V>>>,
+        step: F,
+    ) -> impl Iterator<Item = NonNull<Node<K, V>>>
+    where
+        F: Fn(&Node<K, V>) -> Option<NonNull<Node<K, V>>>,
+    {
+        std::iter::successors(start, move |node| step(unsafe { node.as_ref() }))
+            .filter(|node| unsafe { !node.as_ref().pinned })
+    }
+
+    // splitmix64: tiny, decent quality, and fine with any seed including 0.
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn record_access(&self, k: &K, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.set(counter.get() + 1);
+        if let Some(window) = &self.stats_window {
+            let window = &mut *window.borrow_mut();
+            if window.outcomes.len() == window.len && window.outcomes.pop_front() == Some(true) {
+                window.hits -= 1;
+            }
+            window.outcomes.push_back(hit);
+            window.hits += usize::from(hit);
+        }
+        if let Some(on_access) = &self.on_access {
+            (on_access.borrow_mut())(k, hit);
+        }
+    }
+
+    fn adapt(&mut self) {
+        let Some(adaptive) = &mut self.adaptive else {
+            return;
+        };
+
+        let lookups = adaptive.hits + adaptive.misses;
+        if lookups < self.cap as u64 {
+            return;
+        }
+
+        let hit_rate = adaptive.hits as f64 / lookups as f64;
+        // A manual `set_cap` may have left the cap above `max_cap`; adapting
+        // only ever grows, so it leaves such a cap alone.
+        if hit_rate < adaptive.target_hit_rate
+            && adaptive.evictions > 0
+            && self.cap < adaptive.max_cap
+        {
+            let old = self.cap;
+            self.cap = (self.cap + (self.cap / 2).max(1)).min(adaptive.max_cap);
+            adaptive.record(old, self.cap, CapChangeReason::Adaptive);
+        }
+        adaptive.hits = 0;
+        adaptive.misses = 0;
+        adaptive.evictions = 0;
+    }
+
+    // Inserts a node whose key is known to be absent, evicting first if full.
+    // The map goes first: it runs the user's `Hash`/`Eq`, and if those panic
+    // the node must not already be linked.
+    fn insert_node(&mut self, node: NonNull<Node<K, V>>) -> Option<(K, V)> {
+        let evicted = if self.is_full() {
+            self.evict(EvictionReason::Capacity)
+        } else {
+            None
+        };
+
+        self.map.insert(KeyRef(node), node);
+        self.attach(node);
+        evicted
+    }
+
+    // Appends a key known to be absent at the LRU end, without evicting. Used
+    // to rebuild a cache from entries listed MRU first.
+    fn push_back_new(&mut self, k: K, v: V) -> NonNull<Node<K, V>> {
+        let node = self.alloc_node(k, v);
+        self.map.insert(KeyRef(node), node);
+        self.attach_tail(node);
+        node
+    }
+
+    fn pop_head(&mut self) -> Option<(K, V)> {
+        let head = self.head?;
+        Some(self.remove_node(head))
+    }
+
+    fn pop_tail(&mut self) -> Option<(K, V)> {
+        let tail = self.tail?;
+        Some(self.remove_node(tail))
+    }
+
+    fn remove_node(&mut self, node: NonNull<Node<K, V>>) -> (K, V) {
+        // Check before `detach` touches the node, which would already be a
+        // use-after-free.
+        #[cfg(debug_assertions)]
+        assert!(
+            self.live.contains(&node),
+            "removing a node that was already freed"
+        );
+
+        // Unmap before unlinking, in case the user's `Hash`/`Eq` panics.
+        self.map.remove(&KeyRef(node));
+        self.detach(node);
+        let node = self.free_node(node);
+        (node.k, node.v)
+    }
+
+    // Re-keys every node into a new map, which also gets a fresh seed.
+    fn rebuild_map(&mut self, capacity: usize) {
+        let mut map = HashMap::with_capacity_and_hasher(capacity, RandomState::new());
+        let mut cur = self.head;
+        while let Some(node) = cur {
+            map.insert(KeyRef(node), node);
+            cur = unsafe { node.as_ref().next };
+        }
+
+        assert_eq!(map.len(), self.map.len(), "list and map disagree");
+        self.map = map;
+    }
+
+    // Records a hit on `node`: CLOCK only sets its reference bit, every other
+    // policy moves it to the MRU end.
+    fn touch(&mut self, node: NonNull<Node<K, V>>) {
+        if self.policy == Policy::Clock {
+            unsafe { (*node.as_ptr()).referenced = true };
+        } else {
+            self.detach(node);
+            self.attach(node);
+        }
+    }
+
+    fn attach(&mut self, mut node: NonNull<Node<K, V>>) {
+        match self.head {
+            Some(mut head) => {
+                unsafe {
+                    head.as_mut().prev = Some(node);
+                    node.as_mut().next = Some(head);
+                    node.as_mut().prev = None;
+                }
+                self.head = Some(node);
+            }
+            None => {
+                unsafe {
+                    node.as_mut().prev = None;
+                    node.as_mut().next = None;
+                }
+                self.head = Some(node);
+                self.tail = Some(node);
+            }
+        }
+    }
+}
+
+impl<K, V> LruCache<K, V> {
+    // Every node is allocated here and, apart from bulk frees in `free_all`,
+    // freed in `free_node`. Debug builds track live nodes in between
+    // so a double free panics instead of corrupting memory.
+    fn alloc_node(&mut self, k: K, v: V) -> NonNull<Node<K, V>> {
+        let node = NonNull::from(Box::leak(Box::new(Node::new(k, v))));
+        #[cfg(debug_assertions)]
+        self.live.insert(node);
+        node
+    }
+
+    fn free_node(&mut self, node: NonNull<Node<K, V>>) -> Node<K, V> {
+        #[cfg(debug_assertions)]
+        assert!(self.live.remove(&node), "double free of a cache node");
+        unsafe { *Box::from_raw(node.as_ptr()) }
+    }
+
+    // Frees the whole list in a single pass without touching the map, which
+    // `clear` empties itself and `Drop` doesn't need. Each node's successor
+    // is read before the node is freed, so every node is freed exactly once.
+    fn free_all(&mut self) {
+        #[cfg(debug_assertions)]
+        self.live.clear();
+        self.tail = None;
+        let mut cur = self.head.take();
+        while let Some(node) = cur {
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            cur = node.next;
+        }
+    }
+
+    fn attach_tail(&mut self, mut node: NonNull<Node<K, V>>) {
+        unsafe {
+            node.as_mut().next = None;
+            node.as_mut().prev = self.tail;
+        }
+        match self.tail {
+            Some(mut tail) => unsafe { tail.as_mut().next = Some(node) },
+            None => self.head = Some(node),
+        }
+        self.tail = Some(node);
+    }
+}
+
+impl<K: Hash + Eq, T> LruCache<K, Arc<T>> {
+    /// Promotes `k` and returns a clone of its `Arc`, which stays valid even
+    /// after the entry is evicted.
+    pub fn get_arc(&mut self, k: &K) -> Option<Arc<T>> {
+        self.get(k).cloned()
+    }
+}
+
+impl<K: Hash + Eq> LruCache<K, Vec<u8>> {
+    /// Evicts from the LRU end until the values' total byte length is at
+    /// most `budget`, returning the evicted entries oldest first.
+    ///
+    /// This is a one-shot trim for memory pressure; it doesn't change the
+    /// capacity. Only value bytes are counted, not keys or node overhead.
+    pub fn prune_to_memory(&mut self, budget: usize) -> Vec<(K, Vec<u8>)> {
+        let mut total: usize = self.iter().map(|(_, v)| v.len()).sum();
+        let mut evicted = Vec::new();
+        while total > budget {
+            let Some((k, v)) = self.pop_tail() else {
+                break;
+            };
+            total -= v.len();
+            evicted.push((k, v));
+        }
+        evicted
+    }
+
+    /// Returns the smallest, largest and mean value length in a single pass,
+    /// e.g. to tune a `prune_to_memory` budget. Recency is unchanged.
+    pub fn value_size_stats(&self) -> SizeStats {
+        if self.is_empty() {
+            return SizeStats::default();
+        }
+        let (mut min, mut max, mut total) = (usize::MAX, 0, 0);
+        for (_, v) in self.iter() {
+            min = min.min(v.len());
+            max = max.max(v.len());
+            total += v.len();
+        }
+        SizeStats {
+            min,
+            max,
+            mean: total as f64 / self.len() as f64,
+        }
+    }
+}
+
+impl<V> LruCache<String, V> {
+    /// Removes every entry whose key starts with `prefix`, returning how many
+    /// were removed, e.g. everything cached under a path.
+    pub fn invalidate_prefix(&mut self, prefix: &str) -> usize {
+        self.evict_where(|k, _| k.starts_with(prefix))
+    }
+}
+
+/// Iterator over a cache's entries in MRU→LRU order, see [`LruCache::iter`].
+pub struct Iter<'a, K, V> {
+    head: Option<NonNull<Node<K, V>>>,
+    tail: Option<NonNull<Node<K, V>>>,
+    len: usize,
+    marker: PhantomData<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.head.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.head = node.next;
+            (&node.k, &node.v)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.tail.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.tail = node.prev;
+            (&node.k, &node.v)
+        })
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+/// Consuming iterator yielding entries in MRU→LRU order.
+///
+/// Each node is freed as it is yielded; whatever is left when the iterator
+/// is dropped is freed along with the cache it owns.
+pub struct IntoIter<K, V> {
+    cache: LruCache<K, V>,
+}
+
+impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cache.pop_head()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.cache.len(), Some(self.cache.len()))
+    }
+}
+
+impl<K: Hash + Eq, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.cache.pop_tail()
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Hash + Eq, V> IntoIterator for LruCache<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter { cache: self }
+    }
+}
+
+/// A borrow-only handle to a cache, see [`LruCache::view`].
+///
+/// It only exposes lookups that never promote, so holding one can't change
+/// which entry is evicted next.
+pub struct CacheView<'a, K, V> {
+    cache: &'a LruCache<K, V>,
+}
+
+impl<K, V> Clone for CacheView<'_, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for CacheView<'_, K, V> {}
+
+impl<'a, K: Hash + Eq, V> CacheView<'a, K, V> {
+    pub fn peek(&self, k: &K) -> Option<&'a V> {
+        self.cache.peek(k)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        self.cache.iter()
+    }
+}
+
+/// Returned by [`LruCache::try_insert`] when the key is already present.
+#[derive(Debug)]
+pub struct OccupiedError<'a, K, V> {
+    /// The key that was not inserted.
+    pub key: K,
+    /// The value that was not inserted.
+    pub value: V,
+    /// The value already stored under `key`.
+    pub existing: &'a V,
+}
+
+/// A snapshot taken by [`LruCache::checkpoint`].
+#[derive(Debug, Clone)]
+pub struct Checkpoint<K, V> {
+    // MRU first.
+    entries: Vec<SavedEntry<K, V>>,
+    cap: usize,
+}
+
+#[derive(Debug, Clone)]
+struct SavedEntry<K, V> {
+    k: K,
+    v: V,
+    accesses: u64,
+    pinned: bool,
+}
+
+/// Restores a cache's capacity on drop, see [`LruCache::with_temp_cap`].
+pub struct CapGuard<'a, K: Hash + Eq, V> {
+    cache: &'a mut LruCache<K, V>,
+    original: usize,
+}
+
+impl<K: Hash + Eq, V> Deref for CapGuard<'_, K, V> {
+    type Target = LruCache<K, V>;
+
+    fn deref(&self) -> &LruCache<K, V> {
+        self.cache
+    }
+}
+
+impl<K: Hash + Eq, V> DerefMut for CapGuard<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut LruCache<K, V> {
+        self.cache
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for CapGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.cache.set_cap(self.original);
+    }
+}
+
+// Empties the cache on drop, so `LruCache::drain_into` leaves it empty even
+// if the callback panics.
+struct ClearOnDrop<'a, K: Hash + Eq, V>(&'a mut LruCache<K, V>);
+
+impl<K: Hash + Eq, V> Drop for ClearOnDrop<'_, K, V> {
+    fn drop(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// A view into a single entry of a cache, see [`LruCache::entry`].
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    cache: &'a mut LruCache<K, V>,
+    node: NonNull<Node<K, V>>,
+}
+
+pub struct VacantEntry<'a, K, V> {
+    cache: &'a mut LruCache<K, V>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, v: V) -> &'a mut V {
+        self.or_insert_evicting(v).0
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    /// Like `or_insert`, but also hands back the entry evicted to make room,
+    /// so write-back caches can persist it.
+    ///
+    /// The reference borrows the cache for `'a`; the evicted pair is owned
+    /// and no longer part of the cache, so it can outlive that borrow.
+    pub fn or_insert_evicting(self, v: V) -> (&'a mut V, Option<(K, V)>) {
+        match self {
+            Entry::Occupied(entry) => (entry.into_mut(), None),
+            Entry::Vacant(entry) => entry.insert_evicting(v),
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        unsafe { &self.node.as_ref().k }
+    }
+
+    pub fn get(&self) -> &V {
+        unsafe { &self.node.as_ref().v }
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        unsafe { &mut self.node.as_mut().v }
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        unsafe { &mut (*self.node.as_ptr()).v }
+    }
+
+    pub fn insert(&mut self, v: V) -> V {
+        mem::replace(self.get_mut(), v)
+    }
+
+    pub fn remove(self) -> V {
+        let (k, v) = self.cache.remove_node(self.node);
+        self.cache.report_eviction(&k, &v, EvictionReason::Manual);
+        v
+    }
+}
+
+impl<'a, K: Hash + Eq, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(self, v: V) -> &'a mut V {
+        self.insert_evicting(v).0
+    }
+
+    /// Inserts at the MRU end and returns the entry evicted to make room, if
+    /// the cache was full.
+    pub fn insert_evicting(self, v: V) -> (&'a mut V, Option<(K, V)>) {
+        let node = self.cache.alloc_node(self.key, v);
+        let evicted = self.cache.insert_node(node);
+        (unsafe { &mut (*node.as_ptr()).v }, evicted)
+    }
+}
+
+/// Extending puts each item in iteration order, evicting as it goes.
+///
+/// Afterwards the cache holds the last `capacity()` distinct keys seen in
+/// the iterator (plus any older entries if the iterator had fewer), with the
+/// last item at the MRU end. A repeated key counts once, at its final
+/// position, and keeps its final value.
+impl<K: Hash + Eq, V> Extend<(K, V)> for LruCache<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.put(k, v);
+        }
+    }
+}
+
+/// Reads a value with `&cache[&key]`.
+///
+/// `Index` only has `&self`, so this behaves like [`LruCache::peek`], not
+/// [`LruCache::get`]: the entry is **not** promoted. Use `get` when the read
+/// should count as a use.
+///
+/// # Panics
+///
+/// Panics if the key is not present in the cache.
+impl<K: Hash + Eq, V> Index<&K> for LruCache<K, V> {
+    type Output = V;
+
+    fn index(&self, k: &K) -> &V {
+        self.peek(k).expect("key not found")
+    }
+}
+
+// The cache owns its nodes outright and only hands out borrows tied to
+// `&self`/`&mut self`, so it can move between threads whenever its contents
+// can. User-supplied hooks are required to be `Send` for the same reason. It
+// is deliberately not `Sync`: share it behind a lock, e.g. `ShardedLruCache`.
+unsafe impl<K: Send, V: Send> Send for LruCache<K, V> {}
+
+impl<K, V> Drop for LruCache<K, V> {
+    fn drop(&mut self) {
+        self.free_all();
+    }
+}
+
+/// Builds a cache and pre-populates it in order, so the last pair listed is
+/// the most recently used.
+///
+/// ```
+/// use lru_cache::lru_cache;
+///
+/// let cache = lru_cache! { cap: 3; 1 => "a", 2 => "b" };
+/// assert_eq!(cache.capacity(), 3);
+/// assert_eq!(cache.first_key_value(), Some((&2, &"b")));
+/// ```
+#[macro_export]
+macro_rules! lru_cache {
+    (cap: $cap:expr $(; $($k:expr => $v:expr),* $(,)?)?) => {{
+        #[allow(unused_mut)]
+        let mut cache = $crate::LruCache::new($cap);
+        $($(cache.put($k, $v);)*)?
+        cache
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn order<K: Copy, V>(lru: &LruCache<K, V>) -> Vec<K> {
+        let mut keys = Vec::new();
+        let mut cur = lru.head;
+        while let Some(node) = cur {
+            unsafe {
+                keys.push(node.as_ref().k);
+                cur = node.as_ref().next;
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn it_works() {
+        let node = Node::new("k", "v");
+        assert_eq!(node.k, "k");
+        assert_eq!(node.v, "v");
+    }
+
+    #[test]
+    fn it_works2() {
+        let mut m = HashMap::new();
+        m.insert(Node::new(1, 1), 1);
+
+        let v = m.get(&1);
+        assert_eq!(v, Some(&1));
+    }
+
+    #[test]
+    fn it_works3() {
+        let mut lru = LruCache::new(3);
+        println!("it's ok");
+        assert_eq!(lru.put(1, 10), None);
+        println!("put 1");
+        assert_eq!(lru.put(2, 20), None);
+        println!("put 2");
+        assert_eq!(lru.put(3, 30), None);
+        println!("put 3");
+        assert_eq!(lru.get(&1), Some(&10));
+        println!("get 1");
+        // assert_eq!(lru.put(2, 200), Some(20));
+        // println!("put 2 again then we should get the order value of key 2 : 20");
+        // assert_eq!(lru.put(4, 40), None);
+        // println!("put 4 , should delete the ordest value from map ");
+        // assert_eq!(lru.get(&2), Some(&200));
+        // println!("get 2");
+    }
+
+    #[test]
+    fn peek_or_insert_with_does_not_promote() {
+        let mut lru = LruCache::new(3);
+        assert_eq!(*lru.peek_or_insert_with(1, || 10), 10);
+        assert_eq!(*lru.peek_or_insert_with(2, || 20), 20);
+        assert_eq!(*lru.peek_or_insert_with(3, || 30), 30);
+
+        for _ in 0..3 {
+            assert_eq!(*lru.peek_or_insert_with(1, || unreachable!()), 10);
+        }
+
+        assert_eq!(lru.put(4, 40), None);
+        assert_eq!(lru.get(&1), None);
+        assert_eq!(lru.get(&2), Some(&20));
+        assert_eq!(lru.get(&3), Some(&30));
+        assert_eq!(lru.get(&4), Some(&40));
+    }
+
+    #[test]
+    fn memory_report_counts_nodes() {
+        let mut lru = LruCache::new(4);
+        for i in 0..6 {
+            lru.put(i, i * 10);
+        }
+
+        let report = lru.memory_report();
+        assert_eq!(report.nodes, lru.len());
+        assert_eq!(
+            report.node_bytes,
+            lru.len() * mem::size_of::<Node<i32, i32>>()
+        );
+        assert!(report.map_capacity >= lru.len());
+    }
+
+    #[test]
+    fn compact_shrinks_map_and_keeps_order() {
+        let mut lru = LruCache::new(1000);
+        for i in 0..1000 {
+            lru.put(i, i);
+        }
+        for i in 0..997 {
+            assert_eq!(lru.remove(&i), Some(i));
+        }
+        assert_eq!(lru.get(&998), Some(&998));
+
+        let before = lru.memory_report().map_capacity;
+        lru.compact();
+        assert!(lru.memory_report().map_capacity < before);
+        assert_eq!(order(&lru), vec![998, 999, 997]);
+        assert_eq!(lru.get(&997), Some(&997));
+        assert_eq!(lru.remove(&0), None);
+
+        lru.put(1000, 1000);
+        assert_eq!(order(&lru), vec![1000, 997, 998, 999]);
+    }
+
+    #[test]
+    fn index_reads_without_promoting() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, "one");
+        lru.put(2, "two");
+
+        assert_eq!(lru[&1], "one");
+        lru.put(3, "three");
+        assert_eq!(lru.peek(&1), None);
+        assert_eq!(&lru[&2], &"two");
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn index_panics_on_missing_key() {
+        let lru: LruCache<i32, i32> = LruCache::new(2);
+        let _ = lru[&1];
+    }
+
+    #[test]
+    fn get_mut_or_insert_default_counts() {
+        let mut counts: LruCache<&str, u32> = LruCache::new(2);
+        for word in ["a", "b", "a", "a", "b", "c"] {
+            *counts.get_mut_or_insert_default(word) += 1;
+        }
+
+        assert_eq!(counts.peek(&"a"), None);
+        assert_eq!(counts.peek(&"b"), Some(&2));
+        assert_eq!(counts.peek(&"c"), Some(&1));
+    }
+
+    #[test]
+    fn with_nonzero_capacity() {
+        let mut lru = LruCache::with_nonzero_capacity(NonZeroUsize::new(8).unwrap());
+        for i in 0..10 {
+            lru.put(i, i);
+        }
+        assert_eq!(lru.len(), 8);
+        assert_eq!(lru.peek(&1), None);
+        assert_eq!(lru.peek(&2), Some(&2));
+    }
+
+    #[test]
+    fn get_or_try_insert_ref_hit() {
+        let mut lru: LruCache<String, usize> = LruCache::new(2);
+        lru.put("a".to_string(), 1);
+        lru.put("b".to_string(), 2);
+
+        let v = lru.get_or_try_insert_ref("a", || -> Result<_, ()> { unreachable!() });
+        assert_eq!(v, Ok(&1));
+        lru.put("c".to_string(), 3);
+        assert_eq!(lru.peek(&"a".to_string()), Some(&1));
+        assert_eq!(lru.peek(&"b".to_string()), None);
+    }
+
+    #[test]
+    fn get_or_try_insert_ref_ok_miss() {
+        let mut lru: LruCache<String, usize> = LruCache::new(2);
+        let v = lru.get_or_try_insert_ref("abc", || Ok::<_, ()>(("abc".to_string(), 3)));
+        assert_eq!(v, Ok(&3));
+        assert_eq!(lru.peek(&"abc".to_string()), Some(&3));
+    }
+
+    #[test]
+    fn get_or_try_insert_ref_err_miss() {
+        let mut lru: LruCache<String, usize> = LruCache::new(2);
+        let v = lru.get_or_try_insert_ref("abc", || Err("boom"));
+        assert_eq!(v, Err("boom"));
+        assert!(lru.is_empty());
+    }
+
+    #[test]
+    fn put_batch_evicts_once_down_to_cap() {
+        let mut lru = LruCache::new(3);
+        lru.put(0, 0);
+        lru.put_batch((1..=6).map(|i| (i, i * 10)).chain([(4, 400)]));
+
+        assert_eq!(lru.len(), 3);
+        assert_eq!(order(&lru), vec![4, 6, 5]);
+        assert_eq!(lru.peek(&4), Some(&400));
+    }
+
+    #[test]
+    fn first_and_last_key_value() {
+        let mut lru = LruCache::new(3);
+        assert_eq!(lru.first_key_value(), None);
+        assert_eq!(lru.last_key_value(), None);
+
+        lru.put(1, 10);
+        assert_eq!(lru.first_key_value(), Some((&1, &10)));
+        assert_eq!(lru.last_key_value(), Some((&1, &10)));
+
+        lru.put(2, 20);
+        lru.put(3, 30);
+        lru.get(&1);
+        assert_eq!(lru.first_key_value(), Some((&1, &10)));
+        assert_eq!(lru.last_key_value(), Some((&2, &20)));
+        assert_eq!(order(&lru), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn adaptive_capacity_grows_under_misses() {
+        let mut lru = LruCache::with_adaptive(2, 16, 0.5);
+        assert_eq!(lru.capacity(), 2);
+
+        let mut seen = vec![lru.capacity()];
+        for i in 0..200 {
+            if lru.get(&i).is_none() {
+                lru.put(i, i);
+            }
+            seen.push(lru.capacity());
+        }
+
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(lru.capacity(), 16);
+        assert_eq!(lru.len(), 16);
+    }
+
+    #[test]
+    fn adaptive_capacity_never_shrinks_a_manual_cap() {
+        let mut lru = LruCache::with_adaptive(2, 4, 0.9);
+        lru.set_cap(10);
+        for i in 0..10 {
+            lru.put(i, i);
+        }
+        for i in 10..50 {
+            if lru.get(&i).is_none() {
+                lru.put(i, i);
+            }
+        }
+
+        assert_eq!(lru.capacity(), 10);
+        assert_eq!(lru.len(), 10);
+        assert!(lru
+            .cap_history()
+            .iter()
+            .all(|c| c.reason != CapChangeReason::Adaptive));
+    }
+
+    #[test]
+    fn adaptive_capacity_holds_when_hit_rate_is_met() {
+        let mut lru = LruCache::with_adaptive(4, 16, 0.5);
+        for i in 0..4 {
+            lru.put(i, i);
+        }
+        for i in 0..200 {
+            lru.get(&(i % 4));
+            lru.put(i % 4, i);
+        }
+        assert_eq!(lru.capacity(), 4);
+    }
+
+    #[test]
+    fn view_is_read_only() {
+        fn describe(
+            view: CacheView<'_, i32, &'static str>,
+        ) -> (usize, Option<&'static str>, bool, Vec<i32>) {
+            (
+                view.len(),
+                view.peek(&1).copied(),
+                view.contains_key(&4),
+                view.iter().map(|(k, _)| *k).collect(),
+            )
+        }
+
+        let mut lru = LruCache::new(3);
+        lru.put(1, "a");
+        lru.put(2, "b");
+        lru.put(3, "c");
+
+        assert_eq!(describe(lru.view()), (3, Some("a"), false, vec![3, 2, 1]));
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+        assert_eq!(
+            lru.iter().rev().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn evict_where_removes_matches() {
+        let mut lru = LruCache::new(10);
+        for i in 0..10 {
+            lru.put(i, i * 10);
+        }
+
+        assert_eq!(lru.evict_where(|k, _| *k < 3 || *k == 9), 4);
+        assert_eq!(order(&lru), vec![8, 7, 6, 5, 4, 3]);
+        assert_eq!(lru.first_key_value(), Some((&8, &80)));
+        assert_eq!(lru.last_key_value(), Some((&3, &30)));
+
+        assert_eq!(lru.evict_where(|_, v| *v > 1000), 0);
+        assert_eq!(lru.evict_where(|_, _| true), 6);
+        assert!(lru.is_empty());
+        assert_eq!(lru.first_key_value(), None);
+    }
+
+    #[test]
+    fn get_arc_outlives_eviction() {
+        let mut lru = LruCache::new(1);
+        lru.put(1, Arc::new(String::from("payload")));
+
+        let held = lru.get_arc(&1).unwrap();
+        assert_eq!(Arc::strong_count(&held), 2);
+
+        lru.put(2, Arc::new(String::from("other")));
+        assert!(!lru.contains_key(&1));
+        assert_eq!(Arc::strong_count(&held), 1);
+        assert_eq!(*held, "payload");
+        assert_eq!(lru.get_arc(&1), None);
+    }
+
+    #[test]
+    fn dedup_values_keeps_mru_survivor() {
+        let mut lru = LruCache::new(6);
+        lru.put(1, "x");
+        lru.put(2, "y");
+        lru.put(3, "x");
+        lru.put(4, "z");
+        lru.put(5, "y");
+        lru.put(6, "x");
+        lru.get(&1);
+
+        assert_eq!(lru.dedup_values(), 3);
+        assert_eq!(order(&lru), vec![1, 5, 4]);
+        assert_eq!(lru.dedup_values(), 0);
+    }
+
+    #[test]
+    fn put_if_absent_miss_inserts() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+        lru.put(2, 20);
+        assert_eq!(*lru.put_if_absent(3, 30), 30);
+        assert_eq!(order(&lru), vec![3, 2]);
+    }
+
+    #[test]
+    fn put_if_absent_hit_keeps_and_promotes() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+        lru.put(2, 20);
+        assert_eq!(*lru.put_if_absent(1, 100), 10);
+        assert_eq!(order(&lru), vec![1, 2]);
+    }
+
+    #[test]
+    fn recommended_map_capacity_covers_cap() {
+        for cap in [1, 3, 4, 7, 8, 14, 15, 100, 1000, 4096] {
+            let recommended = LruCache::<i32, i32>::recommended_map_capacity(cap);
+            assert!(recommended >= cap);
+
+            let mut lru: LruCache<i32, i32> = LruCache::new(cap);
+            lru.map.reserve(cap);
+            assert_eq!(lru.memory_report().map_capacity, recommended);
+        }
+
+        assert_eq!(LruCache::<i32, i32>::recommended_map_capacity(0), 0);
+        assert_eq!(HashMap::<i32, i32>::with_capacity(0).capacity(), 0);
+        assert_eq!(
+            LruCache::<i32, i32>::recommended_map_capacity(usize::MAX),
+            usize::MAX
+        );
+        assert_eq!(
+            LruCache::<i32, i32>::recommended_map_capacity(usize::MAX / 8 + 1),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn take_by_borrowed_key() {
+        let mut lru: LruCache<String, i32> = LruCache::new(3);
+        lru.put("alpha".to_string(), 1);
+        lru.put("beta".to_string(), 2);
+
+        assert_eq!(lru.take("alpha"), Some(("alpha".to_string(), 1)));
+        assert_eq!(lru.take("alpha"), None);
+        assert_eq!(lru.take("gamma"), None);
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.first_key_value(), lru.last_key_value());
+    }
+
+    #[test]
+    fn ghost_tracks_recent_evictions() {
+        let mut lru = LruCache::with_ghost(2, 2);
+        lru.put(1, 1);
+        lru.put(2, 2);
+        assert!(!lru.was_recently_evicted(&1));
+
+        lru.put(3, 3);
+        assert!(lru.was_recently_evicted(&1));
+        lru.put(4, 4);
+        assert!(lru.was_recently_evicted(&1));
+        assert!(lru.was_recently_evicted(&2));
+
+        lru.put(5, 5);
+        assert!(!lru.was_recently_evicted(&1));
+        assert!(lru.was_recently_evicted(&2));
+        assert!(lru.was_recently_evicted(&3));
+        assert!(!lru.was_recently_evicted(&5));
+
+        lru.remove(&5);
+        assert!(!lru.was_recently_evicted(&5));
+    }
+
+    #[test]
+    fn invalidate_prefix_removes_matching_keys() {
+        let mut lru = LruCache::new(8);
+        for key in ["/a/1", "/b/1", "/a/2", "/ab", "/a/3", "/c"] {
+            lru.put(key.to_string(), key.len());
+        }
+
+        assert_eq!(lru.invalidate_prefix("/a/"), 3);
+        let keys: Vec<_> = lru.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["/c", "/ab", "/b/1"]);
+        assert_eq!(lru.invalidate_prefix("/a/"), 0);
+        assert_eq!(lru.invalidate_prefix(""), 3);
+        assert!(lru.is_empty());
+    }
+
+    #[test]
+    fn utilization_histogram_buckets_puts() {
+        let mut lru = LruCache::with_utilization_histogram(10);
+        for i in 1..=12 {
+            lru.put(i, i);
+        }
+        lru.put(12, 0);
+        assert_eq!(lru.utilization_histogram(), [0, 1, 1, 1, 1, 1, 1, 1, 1, 5]);
+
+        let mut plain = LruCache::new(10);
+        plain.put(1, 1);
+        assert_eq!(plain.utilization_histogram(), [0; 10]);
+    }
+
+    #[test]
+    fn demote_makes_entry_next_victim() {
+        let mut lru = LruCache::new(3);
+        assert!(!lru.demote(&1));
+        lru.put(1, 1);
+        assert!(lru.demote(&1));
+        assert_eq!(order(&lru), vec![1]);
+
+        lru.put(2, 2);
+        lru.put(3, 3);
+        assert!(lru.demote(&3));
+        assert_eq!(order(&lru), vec![2, 1, 3]);
+        assert!(lru.demote(&3));
+        assert_eq!(order(&lru), vec![2, 1, 3]);
+
+        lru.put(4, 4);
+        assert!(!lru.contains_key(&3));
+        assert_eq!(order(&lru), vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn entry_or_insert_evicting_reports_eviction() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+        lru.put(2, 20);
+
+        let (v, evicted) = lru.entry(3).or_insert_evicting(30);
+        *v += 1;
+        assert_eq!(evicted, Some((1, 10)));
+        assert_eq!(order(&lru), vec![3, 2]);
+        assert_eq!(lru.peek(&3), Some(&31));
+    }
+
+    #[test]
+    fn entry_or_insert_evicting_without_eviction() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+
+        let (v, evicted) = lru.entry(2).or_insert_evicting(20);
+        assert_eq!((*v, evicted), (20, None));
+
+        let (v, evicted) = lru.entry(1).or_insert_evicting(100);
+        assert_eq!((*v, evicted), (10, None));
+        assert_eq!(order(&lru), vec![1, 2]);
+
+        *lru.entry(2).or_insert(0) += 5;
+        assert_eq!(lru.peek(&2), Some(&25));
+        match lru.entry(2) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove(), 25),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(lru.len(), 1);
+    }
+
+    #[test]
+    fn extend_keeps_last_cap_distinct_keys() {
+        let mut lru = LruCache::new(10);
+        lru.extend((0..100).map(|i| (i, i)));
+        assert_eq!(order(&lru), (90..=99).rev().collect::<Vec<_>>());
+
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c'), (1, 'd'), (4, 'e'), (4, 'f')]);
+        assert_eq!(order(&lru), vec![4, 1, 3]);
+        assert_eq!(lru.peek(&1), Some(&'d'));
+        assert_eq!(lru.peek(&4), Some(&'f'));
+    }
+
+    #[test]
+    fn try_insert_vacant() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+        lru.put(2, 20);
+        assert_eq!(*lru.try_insert(3, 30).unwrap(), 30);
+        assert_eq!(order(&lru), vec![3, 2]);
+    }
+
+    #[test]
+    fn try_insert_occupied() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 10);
+        lru.put(2, 20);
+
+        let err = lru.try_insert(1, 100).unwrap_err();
+        assert_eq!((err.key, err.value, *err.existing), (1, 100, 10));
+        assert_eq!(order(&lru), vec![2, 1]);
+        assert_eq!(lru.peek(&1), Some(&10));
+    }
+
+    #[test]
+    fn lru_cache_macro_builds_in_order() {
+        let lru = lru_cache! { cap: 3; 1 => "a", 2 => "b", 3 => "c", 4 => "d", };
+        assert_eq!(lru.capacity(), 3);
+        assert_eq!(order(&lru), vec![4, 3, 2]);
+        assert_eq!(lru.peek(&4), Some(&"d"));
+
+        let empty: LruCache<i32, i32> = lru_cache! { cap: 5 };
+        assert_eq!(empty.capacity(), 5);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn into_sorted_vec_sorts_by_key() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(4);
+        for k in [3, 1, 4, 2] {
+            lru.put(k, Rc::clone(&tracker));
+        }
+        lru.get(&1);
+
+        let sorted = lru.into_sorted_vec();
+        assert_eq!(
+            sorted.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(Rc::strong_count(&tracker), 5);
+        drop(sorted);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn into_iter_frees_unconsumed_entries() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(3);
+        for k in 0..3 {
+            lru.put(k, Rc::clone(&tracker));
+        }
+
+        let mut iter = lru.into_iter();
+        assert_eq!(iter.next().map(|(k, _)| k), Some(2));
+        assert_eq!(iter.len(), 2);
+        drop(iter);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn temp_cap_restores_on_drop() {
+        let mut lru = LruCache::new(2);
+        lru.put(0, 0);
+        {
+            let mut burst = lru.with_temp_cap(5);
+            assert_eq!(burst.capacity(), 5);
+            for i in 1..=6 {
+                burst.put(i, i);
+            }
+            assert_eq!(burst.len(), 5);
+        }
+
+        assert_eq!(lru.capacity(), 2);
+        assert_eq!(order(&lru), vec![6, 5]);
+
+        lru.set_cap(1);
+        assert_eq!(order(&lru), vec![6]);
+    }
+
+    #[test]
+    fn mru_and_lru_values() {
+        let mut lru = LruCache::new(4);
+        for i in 1..=4 {
+            lru.put(i, i * 10);
+        }
+
+        assert_eq!(lru.mru_values(2), vec![&40, &30]);
+        assert_eq!(lru.lru_values(2), vec![&10, &20]);
+        assert_eq!(lru.mru_values(10), vec![&40, &30, &20, &10]);
+        assert_eq!(lru.lru_values(10), vec![&10, &20, &30, &40]);
+        assert!(lru.mru_values(0).is_empty());
+        assert_eq!(order(&lru), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn rehash_preserves_entries_and_order() {
+        let mut lru = LruCache::new(8);
+        for i in 0..8 {
+            lru.put(i, i * 2);
+        }
+        lru.get(&3);
+        let before = order(&lru);
+        let capacity = lru.memory_report().map_capacity;
+
+        lru.rehash();
+        assert_eq!(order(&lru), before);
+        assert_eq!(lru.memory_report().map_capacity, capacity);
+        for i in 0..8 {
+            assert_eq!(lru.peek(&i), Some(&(i * 2)));
+        }
+
+        lru.put(8, 16);
+        assert!(!lru.contains_key(&0));
+        assert_eq!(lru.remove(&3), Some(6));
+    }
+
+    #[test]
+    fn contains_key_with_borrowed_key() {
+        let mut lru: LruCache<String, i32> = LruCache::new(2);
+        lru.put("literal".to_string(), 1);
+
+        assert!(lru.contains_key("literal"));
+        assert!(!lru.contains_key("other"));
+        assert!(lru.contains_key(&"literal".to_string()));
+        assert!(lru.view().contains_key("literal"));
+    }
+
+    #[test]
+    fn access_observer_sees_hits_and_misses() {
+        use std::sync::Mutex;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let mut lru = LruCache::with_access_observer(2, move |k: &i32, hit| {
+            sink.lock().unwrap().push((*k, hit));
+        });
+
+        lru.put(1, 10);
+        lru.get(&1);
+        lru.get(&2);
+        if let Some(v) = lru.get_mut(&1) {
+            *v += 1;
+        }
+        lru.peek(&3);
+        let _ = lru[&1];
+        lru.contains_key(&1);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(1, true), (2, false), (1, true), (3, false), (1, true)]
+        );
+    }
+
+    #[test]
+    fn replace_key_keeps_value_and_position() {
+        let mut lru = LruCache::new(3);
+        lru.put(1, "a");
+        lru.put(2, "b");
+        lru.put(3, "c");
+
+        assert!(lru.replace_key(&2, 20));
+        assert_eq!(order(&lru), vec![3, 20, 1]);
+        assert_eq!(lru.peek(&20), Some(&"b"));
+        assert!(!lru.contains_key(&2));
+    }
+
+    #[test]
+    fn replace_key_missing_old() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, "a");
+        assert!(!lru.replace_key(&5, 6));
+        assert_eq!(order(&lru), vec![1]);
+        assert!(!lru.contains_key(&6));
+    }
+
+    #[test]
+    fn replace_key_colliding_new() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, "a");
+        lru.put(2, "b");
+        assert!(!lru.replace_key(&1, 2));
+        assert_eq!(order(&lru), vec![2, 1]);
+        assert_eq!(lru.peek(&1), Some(&"a"));
+        assert_eq!(lru.peek(&2), Some(&"b"));
+    }
+
+    #[test]
+    fn count_by_groups_entries() {
+        let mut lru = LruCache::new(10);
+        for i in 0..7 {
+            lru.put(i, i * 10);
+        }
+
+        let counts = lru.count_by(|k, _| k % 3);
+        assert_eq!(counts, HashMap::from([(0, 3), (1, 2), (2, 2)]));
+        let big = lru.count_by(|_, v| *v >= 30);
+        assert_eq!(big, HashMap::from([(false, 3), (true, 4)]));
+        assert_eq!(order(&lru), (0..7).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stats_readable_through_shared_ref() {
+        fn scrape(lru: &LruCache<i32, i32>) -> (u64, u64, f64) {
+            (lru.hits(), lru.misses(), lru.hit_rate())
+        }
+
+        let mut lru = LruCache::new(2);
+        assert_eq!(scrape(&lru), (0, 0, 0.0));
+
+        lru.put(1, 1);
+        lru.get(&1);
+        lru.get(&2);
+        lru.peek(&1);
+        lru.get_mut(&1);
+        assert_eq!(scrape(&lru), (3, 1, 0.75));
+    }
+
+    #[test]
+    fn prune_to_memory_trims_oldest() {
+        let mut lru = LruCache::new(4);
+        lru.put(1, vec![0; 100]);
+        lru.put(2, vec![0; 50]);
+        lru.put(3, vec![0; 30]);
+        lru.put(4, vec![0; 20]);
+
+        assert!(lru.prune_to_memory(200).is_empty());
+
+        let evicted = lru.prune_to_memory(60);
+        assert_eq!(
+            evicted
+                .iter()
+                .map(|(k, v)| (*k, v.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 100), (2, 50)]
+        );
+        assert_eq!(order(&lru), vec![4, 3]);
+        assert_eq!(lru.capacity(), 4);
+
+        assert_eq!(lru.prune_to_memory(0).len(), 2);
+        assert!(lru.is_empty());
+    }
+
+    #[test]
+    fn insert_computed_two_step_flow() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, "one".to_string());
+        lru.put(2, "two".to_string());
+
+        assert_eq!(lru.get(&3), None);
+        let computed = "three".to_string();
+        assert_eq!(
+            lru.insert_computed(3, computed),
+            Some((1, "one".to_string()))
+        );
+        assert_eq!(order(&lru), vec![3, 2]);
+    }
+
+    #[test]
+    fn insert_computed_after_concurrent_insert() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 1);
+        lru.put(2, 2);
+
+        assert_eq!(lru.get(&3), None);
+        lru.put(3, 30);
+        lru.get(&2);
+
+        assert_eq!(lru.insert_computed(3, 300), None);
+        assert_eq!(order(&lru), vec![3, 2]);
+        assert_eq!(lru.peek(&3), Some(&300));
+        assert_eq!(lru.len(), 2);
+    }
+
+    #[test]
+    fn random_policy_is_deterministic_per_seed() {
+        fn victims(policy: Policy) -> Vec<i32> {
+            let mut lru = LruCache::with_policy(4, policy);
+            (0..20)
+                .filter_map(|i| lru.insert_computed(i, i).map(|(k, _)| k))
+                .collect()
+        }
+
+        let random = victims(Policy::Random { seed: 42 });
+        assert_eq!(random.len(), 16);
+        assert_eq!(random, victims(Policy::Random { seed: 42 }));
+        assert_ne!(random, victims(Policy::Random { seed: 7 }));
+        assert_ne!(random, victims(Policy::Lru));
+        assert_eq!(victims(Policy::Lru), (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn random_policy_put_keeps_new_entry() {
+        let mut lru = LruCache::with_policy(1, Policy::Random { seed: 1 });
+        for i in 0..10 {
+            lru.put(i, i);
+            assert_eq!(lru.peek(&i), Some(&i));
+        }
+        let v = lru.get_or_try_insert_ref(&10, || Ok::<_, ()>((10, 10)));
+        assert_eq!(v, Ok(&10));
+    }
+
+    #[test]
+    fn checkpoint_and_restore() {
+        let mut lru = LruCache::new(3);
+        lru.put(1, "a");
+        lru.put(2, "b");
+        lru.put(3, "c");
+        lru.get(&1);
+        let cp = lru.checkpoint();
+
+        lru.remove(&2);
+        lru.put(4, "d");
+        lru.put(1, "z");
+        lru.set_cap(5);
+
+        lru.restore(cp);
+        assert_eq!(order(&lru), vec![1, 3, 2]);
+        assert_eq!(lru.peek(&1), Some(&"a"));
+        assert_eq!(lru.capacity(), 3);
+        assert!(!lru.contains_key(&4));
+
+        lru.put(5, "e");
+        assert_eq!(order(&lru), vec![5, 1, 3]);
+    }
+
+    #[test]
+    fn restore_sets_capacity_through_set_cap() {
+        let mut small = LruCache::new(1);
+        small.put(1, 'a');
+        let cp = small.checkpoint();
+
+        let mut floored = LruCache::with_min_capacity(8, 4);
+        floored.restore(cp.clone());
+        assert_eq!(floored.capacity(), 4);
+        assert_eq!(order(&floored), vec![1]);
+
+        let mut adaptive = LruCache::with_adaptive(2, 16, 0.5);
+        adaptive.restore(cp);
+        assert_eq!(adaptive.capacity(), 1);
+        let last = adaptive.cap_history().last().unwrap();
+        assert_eq!(
+            (last.old, last.new, last.reason),
+            (2, 1, CapChangeReason::Manual)
+        );
+    }
+
+    #[test]
+    fn clear_frees_entries() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(3);
+        for i in 0..3 {
+            lru.put(i, Rc::clone(&tracker));
+        }
+        lru.clear();
+        assert!(lru.is_empty());
+        assert_eq!(lru.first_key_value(), None);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+        assert_eq!(lru.capacity(), 3);
+    }
+
+    #[test]
+    fn map_values_preserves_order() {
+        let mut lru = LruCache::new(3);
+        lru.put(1, 10);
+        lru.put(2, 20);
+        lru.put(3, 30);
+        lru.get(&2);
+
+        let strings = lru.map_values(|v| v.to_string());
+        assert_eq!(order(&strings), vec![2, 3, 1]);
+        assert_eq!(strings.peek(&3), Some(&"30".to_string()));
+        assert_eq!(strings.capacity(), 3);
+        assert_eq!(order(&lru), vec![2, 3, 1]);
+        assert_eq!(lru.peek(&3), Some(&30));
+    }
+
+    #[test]
+    fn get_and_update_replaces_value() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 1);
+        lru.put(2, 2);
+        assert_eq!(lru.get_and_update(&1, |v| Some(v + 10)), Some(&11));
+        assert_eq!(order(&lru), vec![1, 2]);
+        assert_eq!(lru.peek(&1), Some(&11));
+    }
+
+    #[test]
+    fn get_and_update_keeps_value_but_promotes() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 1);
+        lru.put(2, 2);
+        assert_eq!(lru.get_and_update(&1, |_| None), Some(&1));
+        assert_eq!(order(&lru), vec![1, 2]);
+    }
+
+    #[test]
+    fn get_and_update_absent() {
+        let mut lru: LruCache<i32, i32> = LruCache::new(2);
+        assert_eq!(lru.get_and_update(&1, |_| unreachable!()), None);
+        assert!(lru.is_empty());
+    }
+
+    #[test]
+    fn retain_mut_updates_and_filters() {
+        let mut lru = LruCache::new(4);
+        lru.put('a', 1);
+        lru.put('b', 3);
+        lru.put('c', 2);
+        lru.put('d', 1);
+
+        lru.retain_mut(|_, ttl| {
+            *ttl -= 1;
+            *ttl > 0
+        });
+        assert_eq!(order(&lru), vec!['c', 'b']);
+        assert_eq!(lru.peek(&'b'), Some(&2));
+
+        lru.retain_mut(|_, ttl| {
+            *ttl -= 1;
+            *ttl > 0
+        });
+        assert_eq!(order(&lru), vec!['b']);
+        assert_eq!(lru.first_key_value(), lru.last_key_value());
+    }
+
+    #[test]
+    fn pressure_callback_fires_past_high_water() {
+        use std::sync::Mutex;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let mut lru = LruCache::with_pressure_callback(10, 0.8, move |len, cap| {
+            sink.lock().unwrap().push((len, cap));
+        });
+
+        for i in 0..7 {
+            lru.put(i, i);
+        }
+        assert!(events.lock().unwrap().is_empty());
+
+        for i in 7..11 {
+            lru.put(i, i);
+        }
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(8, 10), (9, 10), (10, 10), (10, 10)]
+        );
+    }
+
+    #[test]
+    fn pop_lru_n_fewer_than_len() {
+        let mut lru = LruCache::new(4);
+        lru.extend((1..=4).map(|i| (i, i)));
+        assert_eq!(lru.pop_lru_n(2), vec![(1, 1), (2, 2)]);
+        assert_eq!(order(&lru), vec![4, 3]);
+    }
+
+    #[test]
+    fn pop_lru_n_exactly_len() {
+        let mut lru = LruCache::new(4);
+        lru.extend((1..=3).map(|i| (i, i)));
+        assert_eq!(lru.pop_lru_n(3), vec![(1, 1), (2, 2), (3, 3)]);
+        assert!(lru.is_empty());
+        assert_eq!(lru.last_key_value(), None);
+    }
+
+    #[test]
+    fn pop_lru_n_more_than_len() {
+        let mut lru = LruCache::new(4);
+        lru.extend((1..=2).map(|i| (i, i)));
+        assert_eq!(lru.pop_lru_n(10), vec![(1, 1), (2, 2)]);
+        assert!(lru.pop_lru_n(1).is_empty());
+    }
+
+    #[test]
+    fn is_full_tracks_eviction_trigger() {
+        let mut lru = LruCache::new(2);
+        assert!(!lru.is_full());
+        lru.put(1, 1);
+        assert!(!lru.is_full());
+        lru.put(2, 2);
+        assert!(lru.is_full());
+        assert_eq!(lru.insert_computed(3, 3), Some((1, 1)));
+        assert!(lru.is_full());
+        lru.remove(&2);
+        assert!(!lru.is_full());
+    }
+
+    #[test]
+    fn try_new_rejects_zero_capacity() {
+        let err = LruCache::<i32, i32>::try_new(0).err();
+        assert_eq!(err, Some(CacheError::ZeroCapacity));
+        assert_eq!(
+            err.unwrap().to_string(),
+            "cache capacity must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn try_new_accepts_positive_capacity() {
+        let mut lru = LruCache::try_new(2).unwrap();
+        lru.put(1, 1);
+        assert_eq!(lru.capacity(), 2);
+        assert_eq!(lru.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cache capacity must be greater than zero")]
+    fn new_panics_on_zero_capacity() {
+        let _ = LruCache::<i32, i32>::new(0);
+    }
+
+    #[test]
+    fn get_or_insert_with_key_passes_key() {
+        let mut lru: LruCache<String, usize> = LruCache::new(2);
+        let mut calls = Vec::new();
+
+        let v = *lru.get_or_insert_with_key("hello".to_string(), |k| {
+            calls.push(k.clone());
+            k.len()
+        });
+        assert_eq!(v, 5);
+
+        let v = *lru.get_or_insert_with_key("hello".to_string(), |k| {
+            calls.push(k.clone());
+            0
+        });
+        assert_eq!(v, 5);
+        assert_eq!(calls, vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn interleaved_removals_free_each_node_once() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(8);
+        for i in 0..8 {
+            lru.put(i, Rc::clone(&tracker));
+        }
+
+        assert!(lru.remove(&3).is_some());
+        assert_eq!(lru.pop_lru_n(2).len(), 2);
+        assert!(lru.take(&3).is_none());
+        assert!(lru.remove(&0).is_none());
+        assert_eq!(lru.evict_where(|k, _| k % 2 == 0), 3);
+        assert!(lru.remove(&7).is_some());
+        assert_eq!(lru.pop_lru_n(10).len(), 1);
+        assert!(lru.remove(&5).is_none());
+
+        assert!(lru.is_empty());
+        #[cfg(debug_assertions)]
+        assert!(lru.live.is_empty());
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already freed")]
+    fn double_free_is_caught() {
+        let mut lru = LruCache::new(2);
+        lru.put(1, 1);
+        let node = lru.head.unwrap();
+        lru.remove_node(node);
+        lru.remove_node(node);
+    }
+
+    #[test]
+    fn clone_with_smaller_cap() {
+        let mut lru = LruCache::new(5);
+        lru.extend((1..=5).map(|i| (i, i * 10)));
+        lru.get(&2);
+
+        let small = lru.clone_with_cap(3);
+        assert_eq!(small.capacity(), 3);
+        assert_eq!(order(&small), vec![2, 5, 4]);
+        assert_eq!(order(&lru), vec![2, 5, 4, 3, 1]);
+    }
+
+    #[test]
+    fn clone_with_larger_cap() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+
+        let mut big = lru.clone_with_cap(4);
+        assert_eq!(order(&big), vec![2, 1]);
+        big.put(3, 'c');
+        big.put(4, 'd');
+        assert_eq!(order(&big), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn same_order_as_requires_matching_order() {
+        let mut a = LruCache::new(3);
+        let mut b = LruCache::new(5);
+        a.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        b.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert!(a.same_order_as(&b));
+
+        b.get(&1);
+        assert!(!a.same_order_as(&b));
+
+        a.get(&1);
+        assert!(a.same_order_as(&b));
+
+        a.put(1, 'z');
+        assert!(!a.same_order_as(&b));
+    }
+
+    #[test]
+    fn range_filters_and_sorts_by_key() {
+        let mut lru = LruCache::new(10);
+        for k in [5, 1, 9, 3, 7] {
+            lru.put(k, k * 10);
+        }
+        let keys =
+            |entries: Vec<(&i32, &i32)>| entries.into_iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+        assert_eq!(keys(lru.range(3..=7)), vec![3, 5, 7]);
+        assert_eq!(keys(lru.range(3..7)), vec![3, 5]);
+        assert_eq!(keys(lru.range(..5)), vec![1, 3]);
+        assert_eq!(keys(lru.range(6..)), vec![7, 9]);
+        assert_eq!(keys(lru.range(..)), vec![1, 3, 5, 7, 9]);
+        assert!(lru.range(10..).is_empty());
+        assert_eq!(lru.range(9..)[0], (&9, &90));
+        assert_eq!(order(&lru), vec![7, 3, 9, 1, 5]);
+    }
+
+    #[test]
+    fn flush_cold_removes_rarely_accessed() {
+        let mut lru = LruCache::new(5);
+        lru.extend((1..=5).map(|i| (i, i)));
+        for _ in 0..3 {
+            lru.get(&1);
+            lru.get_mut(&4);
+        }
+        lru.get(&2);
+        lru.peek(&3);
+        lru.put(5, 50);
+
+        assert_eq!(lru.flush_cold(2), vec![(3, 3), (2, 2), (5, 50)]);
+        assert_eq!(order(&lru), vec![4, 1]);
+        assert!(lru.flush_cold(3).is_empty());
+        assert_eq!(lru.flush_cold(4).len(), 2);
+    }
+
+    #[test]
+    fn stats_window_tracks_recent_lookups() {
+        let mut lru = LruCache::with_stats_window(4, 4);
+        assert_eq!(lru.recent_hit_rate(), 0.0);
+        lru.put(1, 1);
+        for k in [2, 3, 4, 5] {
+            lru.get(&k);
+        }
+        assert_eq!(lru.recent_hit_rate(), 0.0);
+
+        lru.get(&1);
+        lru.get(&1);
+        lru.get(&1);
+        assert_eq!(lru.recent_hit_rate(), 0.75);
+        assert_eq!(lru.hit_rate(), 3.0 / 7.0);
+
+        lru.get(&1);
+        assert_eq!(lru.recent_hit_rate(), 1.0);
+        assert_eq!(LruCache::<i32, i32>::new(1).recent_hit_rate(), 0.0);
+    }
+
+    #[test]
+    fn peek_lru_n_lists_eviction_candidates() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert_eq!(lru.peek_lru_n(2), vec![(&1, &'a'), (&2, &'b')]);
+        assert_eq!(lru.peek_lru_n(3).len(), 3);
+        assert_eq!(lru.peek_lru_n(10), vec![(&1, &'a'), (&2, &'b'), (&3, &'c')]);
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn into_iter_lru_yields_oldest_first() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(3);
+        for k in 1..=4 {
+            lru.put(k, Rc::clone(&tracker));
+        }
+        lru.get(&2);
+
+        let mut iter = lru.into_iter_lru();
+        assert_eq!(iter.next().map(|(k, _)| k), Some(3));
+        assert_eq!(iter.next().map(|(k, _)| k), Some(4));
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(iter);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn rehash_keys_repairs_mutated_keys() {
+        let mut lru = LruCache::new(4);
+        lru.extend((1..=4).map(|i| (i, i * 10)));
+        let node = *lru.map.get(KeyWrapper::from_ref(&2)).unwrap();
+        unsafe { (*node.as_ptr()).k = 20 };
+        assert!(!lru.contains_key(&20));
+
+        lru.rehash_keys();
+        assert_eq!(lru.peek(&20), Some(&20));
+        assert_eq!(order(&lru), vec![4, 3, 20, 1]);
+
+        let node = *lru.map.get(KeyWrapper::from_ref(&1)).unwrap();
+        unsafe { (*node.as_ptr()).k = 3 };
+        lru.rehash_keys();
+        assert_eq!(order(&lru), vec![4, 3, 20]);
+        assert_eq!(lru.peek(&3), Some(&30));
+    }
+
+    #[test]
+    fn saturating_put_refuses_to_evict() {
+        let mut lru = LruCache::new(2);
+        assert_eq!(lru.saturating_put(1, 'a'), Ok(None));
+        assert_eq!(lru.saturating_put(2, 'b'), Ok(None));
+        assert_eq!(lru.saturating_put(3, 'c'), Err((3, 'c')));
+        assert_eq!(lru.saturating_put(1, 'A'), Ok(Some('a')));
+        assert_eq!(order(&lru), vec![1, 2]);
+    }
+
+    #[test]
+    fn fill_order_into_reuses_buffer() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        let mut buf = Vec::new();
+        lru.fill_order_into(&mut buf);
+        assert_eq!(buf, vec![(&3, &'c'), (&2, &'b'), (&1, &'a')]);
+        let capacity = buf.capacity();
+
+        let mut other = LruCache::new(2);
+        other.put(9, 'z');
+        other.fill_order_into(&mut buf);
+        assert_eq!(buf, vec![(&9, &'z')]);
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn contains_all_and_any() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, ()), (2, ()), (3, ())]);
+        assert!(lru.contains_all(&[1, 3]));
+        assert!(lru.contains_any(&[1, 3]));
+        assert!(!lru.contains_all(&[1, 4]));
+        assert!(lru.contains_any(&[4, 1]));
+        assert!(!lru.contains_all(&[4, 5]));
+        assert!(!lru.contains_any(&[4, 5]));
+        assert!(lru.contains_all(&[]));
+        assert!(!lru.contains_any(&[]));
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn drain_into_empties_even_on_panic() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        let mut drained = Vec::new();
+        lru.drain_into(|k, v| drained.push((k, v)));
+        assert_eq!(drained, vec![(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert!(lru.is_empty());
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(3);
+        for k in 1..=3 {
+            lru.put(k, Rc::clone(&tracker));
+        }
+        let mut calls = 0;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            lru.drain_into(|_, _| {
+                calls += 1;
+                if calls == 2 {
+                    panic!("flush failed");
+                }
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+        assert!(lru.is_empty());
+        assert_eq!(Rc::strong_count(&tracker), 1);
+        lru.put(4, Rc::clone(&tracker));
+        assert_eq!(lru.len(), 1);
+    }
+
+    #[test]
+    fn hottest_and_coldest_by_access_count() {
+        let mut lru = LruCache::new(4);
+        assert_eq!(lru.hottest(), None);
+        lru.extend((1..=4).map(|i| (i, i)));
+        for k in [2, 2, 3, 3, 1] {
+            lru.get(&k);
+        }
+        assert_eq!(lru.hottest(), Some((&3, &3)));
+        assert_eq!(lru.coldest(), Some((&4, &4)));
+        lru.get(&4);
+        assert_eq!(lru.coldest(), Some((&1, &1)));
+        assert_eq!(order(&lru), vec![4, 1, 3, 2]);
+    }
+
+    #[test]
+    fn pinned_entries_survive_eviction() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert!(lru.pin(&1));
+        assert!(!lru.pin(&9));
+        lru.put(4, 'd');
+        lru.put(5, 'e');
+        assert_eq!(order(&lru), vec![5, 4, 1]);
+
+        lru.pin(&4);
+        lru.pin(&5);
+        lru.put(6, 'f');
+        assert_eq!(order(&lru), vec![6, 5, 4, 1]);
+        lru.set_cap(2);
+        assert_eq!(order(&lru), vec![5, 4, 1]);
+
+        assert!(lru.unpin(&1));
+        lru.put(7, 'g');
+        assert_eq!(order(&lru), vec![7, 5, 4]);
+    }
+
+    #[test]
+    fn random_policy_skips_pinned_entries() {
+        let mut lru = LruCache::with_policy(4, Policy::Random { seed: 3 });
+        lru.extend((0..4).map(|i| (i, i)));
+        lru.pin(&0);
+        lru.pin(&2);
+        for k in 4..20 {
+            lru.put(k, k);
+            assert!(lru.contains_all(&[0, 2]));
+        }
+        assert_eq!(lru.len(), 4);
+    }
+
+    #[test]
+    fn restore_keeps_pins_and_access_counts() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        lru.pin(&1);
+        lru.get(&2);
+        lru.get(&2);
+        let cp = lru.checkpoint();
+
+        lru.clear();
+        lru.restore(cp);
+        lru.put(3, 'c');
+        assert_eq!(order(&lru), vec![3, 1]);
+
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        lru.get(&1);
+        lru.get(&1);
+        lru.get(&3);
+        let cp = lru.checkpoint();
+        lru.restore(cp);
+        assert_eq!(lru.hottest(), Some((&1, &'a')));
+        assert_eq!(lru.flush_cold(1), vec![(2, 'b')]);
+    }
+
+    #[test]
+    fn value_size_stats_summarizes_buffers() {
+        let mut lru: LruCache<i32, Vec<u8>> = LruCache::new(4);
+        assert_eq!(lru.value_size_stats(), SizeStats::default());
+        lru.put(1, vec![0; 10]);
+        lru.put(2, vec![0; 2]);
+        lru.put(3, vec![0; 6]);
+        assert_eq!(
+            lru.value_size_stats(),
+            SizeStats {
+                min: 2,
+                max: 10,
+                mean: 6.0
+            }
+        );
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn try_get_reports_misses() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        assert_eq!(lru.try_get(&1), Ok(&'a'));
+        assert_eq!(order(&lru), vec![1, 2]);
+        assert_eq!(lru.try_get(&3), Err(GetError::NotFound));
+        assert_eq!(lru.misses(), 1);
+        assert_eq!(GetError::NotFound.to_string(), "key not found in cache");
+    }
+
+    #[test]
+    fn for_each_visits_mru_first() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 10), (2, 20), (3, 30)]);
+        let mut seen = Vec::new();
+        let mut sum = 0;
+        lru.for_each(|k, v| {
+            seen.push(*k);
+            sum += v;
+        });
+        assert_eq!(seen, vec![3, 2, 1]);
+        assert_eq!(sum, 60);
+
+        lru.for_each_mut(|k, v| *v += k);
+        assert_eq!(lru.peek(&2), Some(&22));
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn for_working_set_adds_headroom() {
+        assert_eq!(
+            LruCache::<i32, i32>::for_working_set(100, 0.25).capacity(),
+            125
+        );
+        assert_eq!(
+            LruCache::<i32, i32>::for_working_set(10, 0.05).capacity(),
+            11
+        );
+        assert_eq!(
+            LruCache::<i32, i32>::for_working_set(100, 0.1).capacity(),
+            110
+        );
+        assert_eq!(LruCache::<i32, i32>::for_working_set(0, 0.5).capacity(), 1);
+        assert!(
+            std::panic::catch_unwind(|| LruCache::<i32, i32>::for_working_set(10, -0.1)).is_err()
+        );
+    }
+
+    #[test]
+    fn move_to_transfers_between_caches() {
+        let mut l1 = LruCache::new(2);
+        let mut l2 = LruCache::new(2);
+        l1.extend([(1, 'a'), (2, 'b')]);
+        l2.extend([(3, 'c'), (4, 'd')]);
+
+        assert!(l1.move_to(&1, &mut l2));
+        assert!(!l1.move_to(&1, &mut l2));
+        assert_eq!(order(&l1), vec![2]);
+        assert_eq!(order(&l2), vec![1, 4]);
+        assert_eq!(l2.peek(&1), Some(&'a'));
+    }
+
+    #[test]
+    fn invalidate_by_value_removes_matches() {
+        let mut lru = LruCache::new(4);
+        lru.extend([(1, 200), (2, 404), (3, 200), (4, 404)]);
+        assert_eq!(lru.invalidate_by_value(|&status| status == 404), 2);
+        assert_eq!(order(&lru), vec![3, 1]);
+        assert_eq!(lru.invalidate_by_value(|&status| status == 500), 0);
+    }
+
+    #[test]
+    fn get_or_load_reads_through() {
+        struct Squares(Cell<usize>);
+
+        impl Loader<u32, u32> for Squares {
+            fn load(&self, k: &u32) -> Option<u32> {
+                self.0.set(self.0.get() + 1);
+                (*k < 10).then(|| k * k)
+            }
+        }
+
+        let loader = Squares(Cell::new(0));
+        let mut lru = LruCache::new(2);
+        assert_eq!(lru.get_or_load(3, &loader), Some(&9));
+        assert_eq!(lru.get_or_load(3, &loader), Some(&9));
+        assert_eq!(loader.0.get(), 1);
+        assert_eq!(lru.get_or_load(12, &loader), None);
+        assert_eq!(loader.0.get(), 2);
+        assert_eq!(lru.len(), 1);
+
+        assert_eq!(lru.get_or_load(5, &|k: &u32| Some(k + 1)), Some(&6));
+        assert_eq!(order(&lru), vec![5, 3]);
+    }
+
+    #[test]
+    fn promote_where_keeps_relative_order() {
+        let mut lru = LruCache::new(6);
+        lru.extend((1..=6).map(|i| (i, i)));
+        assert_eq!(lru.promote_where(|k, _| k % 2 == 1), 3);
+        assert_eq!(order(&lru), vec![5, 3, 1, 6, 4, 2]);
+        assert_eq!(lru.promote_where(|_, _| false), 0);
+        lru.put(7, 7);
+        assert_eq!(lru.peek(&2), None);
+    }
+
+    #[test]
+    fn min_capacity_clamps_shrinking() {
+        let mut lru = LruCache::with_min_capacity(4, 2);
+        lru.extend((1..=4).map(|i| (i, i)));
+        lru.set_cap(1);
+        assert_eq!(lru.capacity(), 2);
+        assert_eq!(order(&lru), vec![4, 3]);
+        lru.set_cap(0);
+        assert_eq!(lru.capacity(), 2);
+        lru.set_cap(8);
+        assert_eq!(lru.capacity(), 8);
+    }
+
+    #[test]
+    fn min_capacity_chains_onto_other_options() {
+        let mut lru = LruCache::with_policy(4, Policy::Clock).min_capacity(3);
+        lru.extend((1..=4).map(|i| (i, i)));
+        lru.set_cap(1);
+        assert_eq!(lru.capacity(), 3);
+        assert_eq!(order(&lru), vec![4, 3, 2]);
+
+        let mut lru: LruCache<i32, i32> = LruCache::with_adaptive(4, 16, 0.5).min_capacity(2);
+        lru.set_cap(1);
+        assert_eq!(lru.capacity(), 2);
+        assert_eq!(lru.cap_history().len(), 1);
+    }
+
+    #[test]
+    fn map_view_exposes_contents() {
+        use std::collections::BTreeMap;
+
+        let mut lru = LruCache::new(3);
+        lru.extend([(3, 'c'), (1, 'a'), (2, 'b'), (4, 'd')]);
+        let view: BTreeMap<_, _> = lru.map_view().collect();
+        assert_eq!(view, BTreeMap::from([(&1, &'a'), (&2, &'b'), (&4, &'d')]));
+        assert_eq!(order(&lru), vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn remove_many_in_input_order() {
+        let mut lru = LruCache::new(3);
+        lru.extend([
+            ("a".to_string(), 1),
+            ("b".to_string(), 2),
+            ("c".to_string(), 3),
+        ]);
+        assert_eq!(
+            lru.remove_many(&["c", "x", "a", "c"]),
+            vec![Some(3), None, Some(1), None]
+        );
+        assert_eq!(lru.len(), 1);
+        assert!(lru.contains_key("b"));
+    }
+
+    #[test]
+    fn snapshot_captures_counters() {
+        let mut lru = LruCache::new(4);
+        lru.extend((1..=5).map(|i| (i, i)));
+        lru.get(&1);
+        lru.get(&2);
+        lru.get(&5);
+        lru.remove(&3);
+        lru.set_cap(2);
+        assert_eq!(
+            lru.snapshot(),
+            Snapshot {
+                len: 2,
+                cap: 2,
+                hits: 2,
+                misses: 1,
+                evictions: 2,
+                hit_rate: 2.0 / 3.0,
+                utilization: 1.0,
+            }
+        );
+    }
+
+    #[test]
+    fn insert_all_or_nothing_is_atomic() {
+        let mut lru = LruCache::new(4);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        assert_eq!(
+            lru.insert_all_or_nothing([(3, 'c'), (4, 'd'), (5, 'e')]),
+            Err(vec![(3, 'c'), (4, 'd'), (5, 'e')])
+        );
+        assert_eq!(order(&lru), vec![2, 1]);
+
+        assert_eq!(
+            lru.insert_all_or_nothing([(1, 'A'), (3, 'c'), (4, 'd'), (3, 'C')]),
+            Ok(())
+        );
+        assert_eq!(order(&lru), vec![3, 4, 1, 2]);
+        assert_eq!(lru.peek(&1), Some(&'A'));
+        assert_eq!(lru.peek(&3), Some(&'C'));
+    }
+
+    #[test]
+    fn eviction_callback_reports_reasons() {
+        use std::sync::{Arc, Mutex};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut lru = LruCache::with_eviction_callback(3, move |k: &i32, _: &i32, reason| {
+            sink.lock().unwrap().push((*k, reason));
+        });
+        lru.extend((1..=4).map(|i| (i, i)));
+        lru.remove(&3);
+        lru.remove(&9);
+        lru.put(5, 5);
+        if let Entry::Occupied(entry) = lru.entry(2) {
+            entry.remove();
+        }
+        lru.set_cap(1);
+        lru.clear();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, EvictionReason::Capacity),
+                (3, EvictionReason::Manual),
+                (2, EvictionReason::Manual),
+                (4, EvictionReason::Resize),
+            ]
+        );
+    }
+
+    #[test]
+    fn dropping_a_large_cache_frees_every_node() {
+        use std::rc::Rc;
+        use std::time::{Duration, Instant};
+
+        let tracker = Rc::new(());
+        let mut lru = LruCache::new(1_000_000);
+        for k in 0..1_000_000 {
+            lru.put(k, Rc::clone(&tracker));
+        }
+        assert_eq!(Rc::strong_count(&tracker), 1_000_001);
+
+        let start = Instant::now();
+        drop(lru);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+        // Generous bound; a quadratic drop would take hours.
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn get_with_controls_promotion() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        assert_eq!(lru.get_with(&1, false), Some(&'a'));
+        lru.put(3, 'c');
+        assert_eq!(order(&lru), vec![3, 2]);
+
+        assert_eq!(lru.get_with(&2, true), Some(&'b'));
+        lru.put(4, 'd');
+        assert_eq!(order(&lru), vec![4, 2]);
+        assert_eq!(lru.get_with(&9, true), None);
+    }
+
+    #[test]
+    fn reset_restores_pristine_state() {
+        let mut lru = LruCache::with_stats_window(2, 4);
+        lru.extend((1..=3).map(|i| (i, i)));
+        lru.get(&3);
+        lru.get(&1);
+
+        lru.reset(5);
+        assert!(lru.is_empty());
+        assert_eq!(lru.iter().next(), None);
+        assert_eq!(lru.capacity(), 5);
+        assert_eq!((lru.hits(), lru.misses(), lru.evictions()), (0, 0, 0));
+        assert_eq!(lru.recent_hit_rate(), 0.0);
+
+        lru.extend((1..=5).map(|i| (i, i)));
+        assert_eq!(order(&lru), vec![5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn get_or_insert_ok_never_caches_errors() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 10), (2, 20)]);
+        assert_eq!(
+            lru.get_or_insert_ok(3, || Err::<i32, _>("timeout")),
+            Err("timeout")
+        );
+        assert_eq!(order(&lru), vec![2, 1]);
+
+        assert_eq!(lru.get_or_insert_ok(3, || Ok::<_, &str>(30)), Ok(&30));
+        assert_eq!(lru.get_or_insert_ok(2, || Err("unused")), Ok(&20));
+        assert_eq!(order(&lru), vec![2, 3]);
+    }
+
+    #[test]
+    fn peek_nth_indexes_by_recency() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert_eq!(lru.peek_nth(0), Some((&3, &'c')));
+        assert_eq!(lru.peek_nth(1), Some((&2, &'b')));
+        assert_eq!(lru.peek_nth(lru.len() - 1), Some((&1, &'a')));
+        assert_eq!(lru.peek_nth(3), None);
+        assert_eq!(order(&lru), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn clock_policy_gives_second_chances() {
+        let mut clock = LruCache::with_policy(2, Policy::Clock);
+        clock.extend([(1, ()), (2, ())]);
+        clock.get(&1);
+        assert_eq!(order(&clock), vec![2, 1]);
+        clock.put(3, ());
+        assert_eq!(order(&clock), vec![3, 1]);
+
+        // Both referenced: strict LRU keeps the last one used, CLOCK sweeps
+        // in list order and evicts the oldest insert.
+        let mut lru = LruCache::new(2);
+        let mut clock = LruCache::with_policy(2, Policy::Clock);
+        for cache in [&mut lru, &mut clock] {
+            cache.extend([(1, ()), (2, ())]);
+            cache.get(&2);
+            cache.get(&1);
+            cache.put(3, ());
+        }
+        assert_eq!(order(&lru), vec![3, 1]);
+        assert_eq!(order(&clock), vec![3, 2]);
+    }
+
+    #[test]
+    fn clock_policy_counter_hits_set_the_reference_bit() {
+        let mut clock: LruCache<i32, u32> = LruCache::with_policy(2, Policy::Clock);
+        *clock.get_mut_or_insert_default(1) += 1;
+        *clock.get_mut_or_insert_default(2) += 1;
+        *clock.get_mut_or_insert_default(1) += 1;
+        assert_eq!(order(&clock), vec![2, 1]);
+
+        clock.put(3, 0);
+        assert_eq!(order(&clock), vec![3, 1]);
+        assert_eq!(clock.peek(&1), Some(&2));
+    }
+
+    #[test]
+    fn drain_oldest_while_stops_at_first_mismatch() {
+        let mut lru = LruCache::new(5);
+        // Values are ages in seconds.
+        lru.extend([(1, 90), (2, 75), (3, 10), (4, 80), (5, 5)]);
+        assert_eq!(
+            lru.drain_oldest_while(|_, &age| age > 60),
+            vec![(1, 90), (2, 75)]
+        );
+        assert_eq!(order(&lru), vec![5, 4, 3]);
+        assert!(lru.drain_oldest_while(|_, &age| age > 60).is_empty());
+        assert_eq!(lru.drain_oldest_while(|_, _| true).len(), 3);
+    }
+
+    #[test]
+    fn bucket_stats_reports_load() {
+        let mut lru = LruCache::new(100);
+        lru.extend((0..50).map(|i| (i, i)));
+        let stats = lru.bucket_stats();
+        assert_eq!(stats.entries, 50);
+        assert!(stats.capacity >= 50);
+        assert!(stats.load_factor > 0.0 && stats.load_factor <= 1.0);
+        assert_eq!(stats.load_factor, 50.0 / stats.capacity as f64);
+    }
+
+    #[test]
+    fn put_or_merge_accumulates() {
+        let mut lru = LruCache::new(2);
+        lru.put_or_merge("a", 1, |sum, n| *sum += n);
+        lru.put_or_merge("b", 10, |sum, n| *sum += n);
+        lru.put_or_merge("a", 2, |sum, n| *sum += n);
+        lru.put_or_merge("a", 3, |sum, n| *sum += n);
+        assert_eq!(lru.peek(&"a"), Some(&6));
+        assert_eq!(order(&lru), vec!["a", "b"]);
+        lru.put_or_merge("c", 100, |sum, n| *sum += n);
+        assert_eq!(order(&lru), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn to_vec_clones_in_recency_order() {
+        let mut lru = LruCache::new(3);
+        lru.extend([
+            (1, "a".to_string()),
+            (2, "b".to_string()),
+            (3, "c".to_string()),
+        ]);
+        lru.get(&1);
+        let snapshot = lru.to_vec();
+        assert_eq!(
+            snapshot,
+            vec![
+                (1, "a".to_string()),
+                (3, "c".to_string()),
+                (2, "b".to_string())
+            ]
+        );
+        assert_eq!(order(&lru), vec![1, 3, 2]);
+        assert_eq!(lru.len(), 3);
+    }
+
+    #[test]
+    fn set_replaces_only_present_keys() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        assert_eq!(lru.set(&1, 'A'), Ok('a'));
+        assert_eq!(order(&lru), vec![1, 2]);
+        assert_eq!(lru.peek(&1), Some(&'A'));
+        assert_eq!(lru.set(&3, 'c'), Err('c'));
+        assert_eq!(order(&lru), vec![1, 2]);
+    }
+
+    #[test]
+    fn iter_from_walks_toward_lru() {
+        let mut lru = LruCache::new(3);
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        let keys = |k| {
+            lru.iter_from(&k)
+                .map(|iter| iter.map(|(k, _)| *k).collect::<Vec<_>>())
+        };
+        assert_eq!(keys(3), Some(vec![3, 2, 1]));
+        assert_eq!(keys(2), Some(vec![2, 1]));
+        assert_eq!(keys(1), Some(vec![1]));
+        assert_eq!(keys(9), None);
+    }
+
+    #[test]
+    fn cap_history_records_resizes() {
+        let mut lru = LruCache::with_adaptive(2, 16, 0.5);
+        for i in 0..200 {
+            if lru.get(&i).is_none() {
+                lru.put(i, i);
+            }
+        }
+        lru.set_cap(8);
+        lru.set_cap(8);
+
+        let changes: Vec<_> = lru
+            .cap_history()
+            .iter()
+            .map(|change| (change.old, change.new, change.reason))
+            .collect();
+        use CapChangeReason::*;
+        assert_eq!(
+            changes,
+            vec![
+                (2, 3, Adaptive),
+                (3, 4, Adaptive),
+                (4, 6, Adaptive),
+                (6, 9, Adaptive),
+                (9, 13, Adaptive),
+                (13, 16, Adaptive),
+                (16, 8, Manual),
+            ]
+        );
+        assert!(lru.cap_history().windows(2).all(|w| w[0].at <= w[1].at));
+        assert!(LruCache::<i32, i32>::new(2).cap_history().is_empty());
+
+        for cap in 1..=40 {
+            lru.set_cap(cap);
+        }
+        assert_eq!(lru.cap_history().len(), 32);
+        assert_eq!(lru.cap_history()[31].new, 40);
+    }
+
+    #[test]
+    fn prefetch_then_get() {
+        let mut lru = LruCache::new(2);
+        lru.extend([(1, 'a'), (2, 'b')]);
+        lru.prefetch(&1);
+        lru.prefetch(&9);
+        assert_eq!((lru.hits(), lru.misses()), (0, 0));
+        assert_eq!(order(&lru), vec![2, 1]);
+        assert_eq!(lru.get(&1), Some(&'a'));
+    }
+
+    #[test]
+    fn clear_and_shrink_releases_the_map() {
+        let mut lru = LruCache::new(64);
+        lru.extend((0..64).map(|i| (i, i)));
+        let map_capacity = lru.memory_report().map_capacity;
+
+        lru.clear();
+        assert_eq!(lru.capacity(), 64);
+        assert_eq!(lru.memory_report().map_capacity, map_capacity);
+
+        lru.extend((0..64).map(|i| (i, i)));
+        lru.clear_and_shrink();
+        assert!(lru.is_empty());
+        assert_eq!(lru.capacity(), 64);
+        assert!(lru.memory_report().map_capacity < map_capacity);
+    }
+
+    #[test]
+    fn key_of_prefers_the_mru_match() {
+        let mut lru = LruCache::new(4);
+        lru.extend([(1, 'x'), (2, 'y'), (3, 'x'), (4, 'z')]);
+        assert_eq!(lru.key_of(&'x'), Some(&3));
+        lru.get(&1);
+        assert_eq!(lru.key_of(&'x'), Some(&1));
+        assert_eq!(lru.key_of(&'q'), None);
+        assert_eq!(order(&lru), vec![1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn sample_keeps_the_hottest_fraction() {
+        let mut lru = LruCache::new(8);
+        lru.extend((1..=8).map(|i| (i, i)));
+        let quarter = lru.sample(0.25);
+        assert_eq!(quarter.capacity(), 2);
+        assert_eq!(order(&quarter), vec![8, 7]);
+
+        lru.pop_lru_n(4);
+        let half = lru.sample(0.5);
+        assert_eq!(half.capacity(), 4);
+        assert_eq!(order(&half), vec![8, 7]);
+
+        assert_eq!(order(&lru.sample(2.0)), vec![8, 7, 6, 5]);
+        let tiny = lru.sample(-1.0);
+        assert_eq!(tiny.capacity(), 1);
+        assert_eq!(order(&tiny), vec![8]);
+        assert_eq!(order(&lru), vec![8, 7, 6, 5]);
+    }
+
+    #[test]
+    fn validate_reports_inconsistencies() {
+        let mut lru = LruCache::new(3);
+        assert_eq!(lru.validate(), Ok(()));
+        lru.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        lru.get(&2);
+        lru.remove(&3);
+        assert_eq!(lru.validate(), Ok(()));
+
+        // Corrupt a link, then put it back so the cache can be dropped.
+        let node = *lru.map.get(KeyWrapper::from_ref(&1)).unwrap();
+        let prev = unsafe { node.as_ref().prev };
+        unsafe { (*node.as_ptr()).prev = None };
+        assert_eq!(
+            lru.validate(),
+            Err("node at position 1 has a broken prev link".to_string())
+        );
+        unsafe { (*node.as_ptr()).prev = prev };
+
+        let removed = lru.map.remove(KeyWrapper::from_ref(&1)).unwrap();
+        assert_eq!(
+            lru.validate(),
+            Err("node at position 1 is not mapped to itself".to_string())
+        );
+        lru.map.insert(KeyRef(removed), removed);
+        assert_eq!(lru.validate(), Ok(()));
     }
 }
@@ -0,0 +1,173 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::LruCache;
+
+/// A cache split into independently locked [`LruCache`] shards, so threads
+/// touching different keys rarely contend on the same lock.
+///
+/// Each key is routed to one shard by its hash, and recency is tracked per
+/// shard rather than globally.
+pub struct ShardedLruCache<K, V> {
+    shards: Vec<Mutex<LruCache<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedLruCache<K, V> {
+    /// Creates `shards` shards sharing a total capacity of `cap`.
+    ///
+    /// Each shard gets `cap / shards` rounded up, so the total capacity can
+    /// exceed `cap` by up to `shards - 1` entries.
+    pub fn new(cap: usize, shards: usize) -> Self {
+        assert!(cap > 0);
+        assert!(shards > 0);
+        let shard_cap = cap.div_ceil(shards);
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(LruCache::new(shard_cap)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns a clone of the value for `k`, promoting it within its shard.
+    pub fn get(&self, k: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(k).get(k).cloned()
+    }
+
+    pub fn put(&self, k: K, v: V) -> Option<V> {
+        self.shard(&k).put(k, v)
+    }
+
+    /// Sums the shard lengths. Shards are locked one after another, so this
+    /// is not a consistent snapshot under concurrent writes.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| lock(shard).is_empty())
+    }
+
+    /// Returns the total capacity across all shards.
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).capacity()).sum()
+    }
+
+    fn shard(&self, k: &K) -> MutexGuard<'_, LruCache<K, V>> {
+        let index = self.hasher.hash_one(k) as usize % self.shards.len();
+        lock(&self.shards[index])
+    }
+}
+
+// `get` and `put` only run user code (`K`'s `Hash`/`Eq`, `V`'s `Drop`) while
+// a shard's map and list agree: the map is updated before the list is
+// relinked. A panic there at worst leaks the entry being inserted, so a
+// poisoned shard is still safe to use.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn splits_capacity_across_shards() {
+        let cache: ShardedLruCache<i32, i32> = ShardedLruCache::new(10, 4);
+        assert_eq!(cache.capacity(), 12);
+        assert!(cache.is_empty());
+
+        cache.put(1, 10);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.put(1, 11), Some(10));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn concurrent_stress() {
+        let cache = Arc::new(ShardedLruCache::new(64, 8));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for i in 0..2000u64 {
+                        let k = (t * 7919 + i * 31) % 500;
+                        if cache.get(&k).is_none() {
+                            cache.put(k, k * 2);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for shard in &cache.shards {
+            let shard = lock(shard);
+            assert!(shard.len() <= shard.capacity());
+            assert!(shard.iter().all(|(k, v)| *v == k * 2));
+        }
+        assert!(cache.len() <= cache.capacity());
+    }
+
+    #[test]
+    fn shard_poisoned_by_a_panicking_hash_stays_usable() {
+        use std::cell::Cell;
+        use std::hash::Hasher;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        thread_local! {
+            // Hash calls left before one panics; 0 disables the countdown.
+            static HASHES_LEFT: Cell<u32> = const { Cell::new(0) };
+        }
+
+        #[derive(PartialEq, Eq)]
+        struct Key(u32);
+
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                HASHES_LEFT.with(|left| match left.get() {
+                    0 => {}
+                    1 => {
+                        left.set(0);
+                        panic!("hash failed");
+                    }
+                    n => left.set(n - 1),
+                });
+                self.0.hash(state);
+            }
+        }
+
+        let cache = ShardedLruCache::new(4, 1);
+        cache.put(Key(1), 10);
+        cache.put(Key(2), 20);
+
+        // Routing and the miss lookup hash fine, the map insert panics.
+        HASHES_LEFT.with(|left| left.set(3));
+        let result = catch_unwind(AssertUnwindSafe(|| cache.put(Key(3), 30)));
+        assert!(result.is_err());
+        assert!(cache.shards[0].is_poisoned());
+
+        let shard = lock(&cache.shards[0]);
+        assert_eq!(
+            shard.iter().map(|(k, v)| (k.0, *v)).collect::<Vec<_>>(),
+            vec![(2, 20), (1, 10)]
+        );
+        drop(shard);
+        assert_eq!(cache.get(&Key(1)), Some(10));
+        assert_eq!(cache.get(&Key(3)), None);
+        cache.put(Key(3), 30);
+        assert_eq!(cache.len(), 3);
+    }
+}